@@ -0,0 +1,426 @@
+// Conversions between SHEQ Values and Rust types, for host functions written as ordinary typed Rust.
+// FromValue reads a Rust value out of a Value, with an error naming the expected type when it does not match.
+// IntoValue builds a Value. HostFn adapts a typed Rust function into a primitive, checking arity and types.
+
+use crate::{BigInt, Rational, Value, serialize};
+
+// FromValue - Rust types that can be read out of a Value
+pub trait FromValue: Sized {
+    // expected - describes the Values that convert, for error messages
+    fn expected() -> String;
+    fn from_value(v: &Value) -> Result<Self, String>;
+}
+
+// IntoValue - Rust types that can be turned into a Value
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+// mismatch - the error for a Value that is not of the expected type
+fn mismatch(expected: &str, got: &Value) -> String {
+    format!("expected {}, got {}", expected, serialize(got))
+}
+
+// exact_integer - the integer held by an exact integer Value
+fn exact_integer(v: &Value) -> Option<&BigInt> {
+    match v {
+        Value::Exact(n) => n.to_integer(),
+        _ => None,
+    }
+}
+
+impl FromValue for Value {
+    fn expected() -> String {
+        "a value".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        Ok(v.clone())
+    }
+}
+
+impl FromValue for f64 {
+    fn expected() -> String {
+        "a number".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        match v {
+            Value::Real(n) => Ok(*n),
+            Value::Exact(n) => Ok(n.to_f64()),
+            other => Err(mismatch(&Self::expected(), other)),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn expected() -> String {
+        "an exact integer".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        exact_integer(v)
+            .and_then(|n| n.to_i64())
+            .ok_or_else(|| mismatch(&Self::expected(), v))
+    }
+}
+
+impl FromValue for usize {
+    fn expected() -> String {
+        "a non-negative exact integer".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        exact_integer(v)
+            .and_then(|n| n.to_i64())
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or_else(|| mismatch(&Self::expected(), v))
+    }
+}
+
+impl FromValue for bool {
+    fn expected() -> String {
+        "a boolean".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        match v {
+            Value::Boolean(b) => Ok(*b),
+            other => Err(mismatch(&Self::expected(), other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn expected() -> String {
+        "a string".into()
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        match v {
+            Value::String(s) => Ok(s.clone()),
+            other => Err(mismatch(&Self::expected(), other)),
+        }
+    }
+}
+
+// Option - void is None, anything else must convert to T. Not false, so an Option<bool> keeps Some(false)
+impl<T: FromValue> FromValue for Option<T> {
+    fn expected() -> String {
+        format!("{} or void", T::expected())
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        match v {
+            Value::Void => Ok(None),
+            other => T::from_value(other)
+                .map(Some)
+                .map_err(|_| mismatch(&Self::expected(), other)),
+        }
+    }
+}
+
+// Vec - a proper list whose elements all convert to T
+impl<T: FromValue> FromValue for Vec<T> {
+    fn expected() -> String {
+        format!("a list of {}", plural(&T::expected()))
+    }
+
+    fn from_value(v: &Value) -> Result<Self, String> {
+        let items = v
+            .list_items()
+            .ok_or_else(|| mismatch(&Self::expected(), v))?;
+        items
+            .iter()
+            .map(T::from_value)
+            .collect::<Result<Vec<T>, String>>()
+            .map_err(|_| mismatch(&Self::expected(), v))
+    }
+}
+
+// tuples - a list of exactly that many elements, up to the arity of a HostFn
+macro_rules! impl_tuple {
+    ($($t:ident $v:ident),+) => {
+        impl<$($t: FromValue),+> FromValue for ($($t,)+) {
+            fn expected() -> String {
+                let items = [$($t::expected()),+];
+                let (last, init) = items.split_last().unwrap();
+                format!("a list of {} and {}", init.join(", "), last)
+            }
+
+            fn from_value(v: &Value) -> Result<Self, String> {
+                match v.list_items().as_deref() {
+                    Some([$($v),+]) => Ok(($(
+                        $t::from_value($v).map_err(|_| mismatch(&Self::expected(), v))?,
+                    )+)),
+                    _ => Err(mismatch(&Self::expected(), v)),
+                }
+            }
+        }
+
+        impl<$($t: IntoValue),+> IntoValue for ($($t,)+) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($t,)+) = self;
+                Value::list(vec![$($t.into_value()),+])
+            }
+        }
+    };
+}
+
+impl_tuple!(A a, B b);
+impl_tuple!(A a, B b, C c);
+impl_tuple!(A a, B b, C c, D d);
+
+// plural - "a number" as "numbers", for describing the elements of a list
+fn plural(expected: &str) -> String {
+    let noun = expected
+        .strip_prefix("a ")
+        .or_else(|| expected.strip_prefix("an "))
+        .unwrap_or(expected);
+    format!("{}s", noun)
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Real(self)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Exact(Rational::from(self))
+    }
+}
+
+impl IntoValue for usize {
+    fn into_value(self) -> Value {
+        Value::Exact(Rational::from(self as i64))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.into())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::list(self.into_iter().map(IntoValue::into_value).collect())
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None => Value::Void,
+        }
+    }
+}
+
+// HostFn - a typed Rust function usable as a host primitive, Args is the tuple of its argument types
+pub trait HostFn<Args> {
+    fn arity(&self) -> usize;
+    // call - converts args, panicking with a SHEQ error naming the function if one does not match
+    fn call(&self, name: &str, args: Vec<Value>) -> Value;
+}
+
+// arg - converts argument i of a host function call
+fn arg<T: FromValue>(name: &str, i: usize, v: &Value) -> T {
+    match T::from_value(v) {
+        Ok(t) => t,
+        Err(e) => panic!("SHEQ: {} argument {}: {}", name, i + 1, e),
+    }
+}
+
+macro_rules! impl_host_fn {
+    ($n:expr $(, $arg:ident $i:tt)*) => {
+        #[allow(non_snake_case)]
+        impl<F, R $(, $arg)*> HostFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            fn arity(&self) -> usize {
+                $n
+            }
+
+            #[allow(unused_variables)]
+            fn call(&self, name: &str, args: Vec<Value>) -> Value {
+                $(let $arg = arg::<$arg>(name, $i, &args[$i]);)*
+                (self)($($arg),*).into_value()
+            }
+        }
+    };
+}
+
+impl_host_fn!(0);
+impl_host_fn!(1, A 0);
+impl_host_fn!(2, A 0, B 1);
+impl_host_fn!(3, A 0, B 1, C 2);
+impl_host_fn!(4, A 0, B 1, C 2, D 3);
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, Registry};
+
+    #[test]
+    fn from_value_works() {
+        assert_eq!(f64::from_value(&Value::Real(1.5)), Ok(1.5));
+        assert_eq!(i64::from_value(&Value::Exact(Rational::from(-3))), Ok(-3));
+        assert_eq!(f64::from_value(&Value::Exact(Rational::from(2))), Ok(2.0));
+        assert_eq!(bool::from_value(&Value::Boolean(true)), Ok(true));
+        assert_eq!(
+            String::from_value(&Value::String("hi".into())),
+            Ok("hi".to_string())
+        );
+        assert_eq!(Option::<f64>::from_value(&Value::Void), Ok(None));
+        assert_eq!(Option::<f64>::from_value(&Value::Real(2.0)), Ok(Some(2.0)));
+    }
+
+    #[test]
+    fn from_value_errors() {
+        assert_eq!(
+            f64::from_value(&Value::String("hi".into())),
+            Err("expected a number, got \"hi\"".to_string())
+        );
+        assert_eq!(
+            usize::from_value(&Value::Exact(Rational::from(-1))),
+            Err("expected a non-negative exact integer, got -1".to_string())
+        );
+        assert_eq!(
+            i64::from_value(&Value::Real(3.0)),
+            Err("expected an exact integer, got 3.0".to_string())
+        );
+        assert_eq!(
+            Option::<String>::from_value(&Value::Real(1.0)),
+            Err("expected a string or void, got 1.0".to_string())
+        );
+    }
+
+    #[test]
+    fn into_value_works() {
+        assert_eq!(2.5.into_value(), Value::Real(2.5));
+        assert_eq!(7usize.into_value(), Value::Exact(Rational::from(7)));
+        assert_eq!("a".into_value(), Value::String("a".into()));
+        assert_eq!(None::<f64>.into_value(), Value::Void);
+    }
+
+    #[test]
+    fn list_conversions() {
+        let v = vec![1i64, 2, 3].into_value();
+        assert_eq!(serialize(&v), "'(1 2 3)");
+        assert_eq!(Vec::<i64>::from_value(&v), Ok(vec![1, 2, 3]));
+        assert_eq!(
+            Vec::<String>::from_value(&v),
+            Err("expected a list of strings, got '(1 2 3)".to_string())
+        );
+        assert_eq!(
+            <(String, f64)>::from_value(&("x", 1.5).into_value()),
+            Ok(("x".to_string(), 1.5))
+        );
+        assert_eq!(
+            <(i64, i64)>::from_value(&v),
+            Err(
+                "expected a list of an exact integer and an exact integer, got '(1 2 3)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn option_bool_round_trips() {
+        for b in [None, Some(false), Some(true)] {
+            assert_eq!(Option::<bool>::from_value(&b.into_value()), Ok(b));
+        }
+    }
+
+    #[test]
+    fn larger_tuples() {
+        let v = ("x", 1.5, true, 2i64).into_value();
+        assert_eq!(serialize(&v), "'(\"x\" 1.5 true 2)");
+        assert_eq!(
+            <(String, f64, bool, i64)>::from_value(&v),
+            Ok(("x".to_string(), 1.5, true, 2))
+        );
+        assert_eq!(
+            <(f64, f64, f64)>::from_value(&v),
+            Err(
+                "expected a list of a number, a number and a number, got '(\"x\" 1.5 true 2)"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn list_host_fn() {
+        let mut sheq = Interpreter::new();
+        sheq.register_fn("sort-numbers", |mut l: Vec<f64>| {
+            l.sort_by(f64::total_cmp);
+            l
+        });
+        assert_eq!(
+            serialize(&sheq.eval_str("{sort-numbers {list 3 1/2 2}}").unwrap()),
+            "'(0.5 2.0 3.0)"
+        );
+    }
+
+    #[test]
+    fn typed_host_fns() {
+        let mut registry = Registry::new();
+        registry
+            .register_fn("hypot", |a: f64, b: f64| (a * a + b * b).sqrt())
+            .register_fn("repeat", |s: String, n: usize| s.repeat(n))
+            .register_fn("answer", || 42.0);
+        let mut sheq = Interpreter::with_env(registry.env());
+        assert_eq!(sheq.eval_str("{hypot 3 4}"), Ok(Value::Real(5.0)));
+        assert_eq!(
+            sheq.eval_str("{repeat \"ab\" 2}"),
+            Ok(Value::String("abab".into()))
+        );
+        assert_eq!(sheq.eval_str("{answer}"), Ok(Value::Real(42.0)));
+    }
+
+    #[test]
+    fn typed_host_fn_type_error() {
+        let mut sheq = Interpreter::new();
+        sheq.register_fn("repeat", |s: String, n: usize| s.repeat(n));
+        assert_eq!(
+            sheq.eval_str("{repeat \"ab\" 1.5}").unwrap_err().message,
+            "SHEQ: repeat argument 2: expected a non-negative exact integer, got 1.5"
+        );
+    }
+
+    #[test]
+    fn typed_host_fn_arity_error() {
+        let mut sheq = Interpreter::new();
+        sheq.register_fn("repeat", |s: String, n: usize| s.repeat(n));
+        assert_eq!(
+            sheq.eval_str("{repeat \"ab\"}").unwrap_err().message,
+            "SHEQ: Incorrect number of arguments to repeat, got 1, expected 2"
+        );
+    }
+}
@@ -0,0 +1,887 @@
+// Macro expansion for SHEQ4: define-syntax with syntax-rules, applied to Sexps before they are parsed.
+//
+// Top ::= ... | {define-syntax id {syntax-rules (id ...) [Pattern Template] ...}}
+//
+// A use {id form ...} is matched against each pattern in turn (the pattern's first element stands for
+// the macro and is ignored), and replaced by the template of the first that matches. The literals
+// match only themselves, _ matches anything, any other symbol is a pattern variable, and an element
+// followed by ... matches zero or more forms.
+//
+// Expansion is hygienic by renaming: every symbol a template introduces becomes a fresh name, name;N,
+// which no program can spell. A fresh name bound by a lambda, let, match or define the template also
+// introduced keeps referring to that binder, so a macro's temporaries never capture the user's
+// variables. A fresh name that is not bound goes back to its original name, so templates can use
+// keywords, primitives and top-level definitions, the names in scope where every macro is defined.
+// A local binding at the use site never captures such a name: a user binder that would is renamed to
+// a fresh name itself, together with the user's references to it.
+//
+// Expanding a use more than MAX_EXPANSION_DEPTH times inside itself is an error, which stops a macro
+// that expands forever.
+//
+// The expansion of a use is marked with the use as written, {;expansion "{use ...}" expansion}, which
+// parses to an ExpansionC. An error raised while parsing or evaluating an expansion names the innermost
+// use it came from.
+
+use std::collections::HashMap;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::is_reserved;
+use crate::parse::{Sexp, unparse_sexp};
+
+// Macros - the syntax-rules macros defined so far, later definitions shadow earlier ones
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    macros: Vec<SyntaxRules>,
+    // number of fresh names made so far, each expansion's are distinct
+    renames: usize,
+    // number of macro uses being expanded, each inside the expansion of the one before
+    depth: usize,
+    // original names of free template names met in the scope of a user binder of that name,
+    // which must be renamed for the template name to keep its top-level meaning
+    captured: Vec<String>,
+}
+
+// SyntaxRules - one macro, its literals and its [pattern template] rules in order
+#[derive(Debug, Clone, PartialEq)]
+struct SyntaxRules {
+    name: String,
+    literals: Vec<String>,
+    rules: Vec<(Sexp, Sexp)>,
+}
+
+// Match - what a pattern variable matched: one form, or one Match per repetition of an ellipsis
+#[derive(Debug, Clone)]
+enum Match {
+    One(Sexp),
+    Many(Vec<Match>),
+}
+
+type Bindings = HashMap<String, Match>;
+
+// Bound - the local binders around a form, innermost last, each as written and as it is output
+type Bound = [(String, String)];
+
+const ELLIPSIS: &str = "...";
+
+// EXPANSION - the head of a marked expansion, no program can spell it since ; starts a comment
+const EXPANSION: &str = ";expansion";
+
+// USE_SITE - what an error raised inside an expansion is followed by, before the use
+const USE_SITE: &str = ", in the expansion of ";
+
+// MAX_EXPANSION_DEPTH - how many uses may expand one inside another, well past any macro that stops
+const MAX_EXPANSION_DEPTH: usize = 250;
+
+impl Macros {
+    pub fn new() -> Self {
+        Macros::default()
+    }
+
+    // expand_top - a define-syntax is recorded and gives None, any other top-level form is expanded
+    pub fn expand_top(&mut self, form: &Sexp) -> Option<Sexp> {
+        self.depth = 0;
+        self.captured.clear();
+        match form {
+            Sexp::List(items) if matches!(items.first(), Some(Sexp::Sym(kw)) if kw == "define-syntax") =>
+            {
+                self.define(form);
+                None
+            }
+            _ => Some(self.walk(form, &[])),
+        }
+    }
+
+    // expand - fully expands every macro use in an expression
+    pub fn expand(&mut self, form: &Sexp) -> Sexp {
+        self.walk(form, &[])
+    }
+
+    // define - records {define-syntax id {syntax-rules (id ...) [Pattern Template] ...}}
+    fn define(&mut self, form: &Sexp) {
+        let ill_formed =
+            || -> ! { panic!("SHEQ: ill-formed define-syntax, got {}", unparse_sexp(form)) };
+        let Sexp::List(items) = form else {
+            ill_formed()
+        };
+        let [_, Sexp::Sym(name), Sexp::List(spec)] = items.as_slice() else {
+            ill_formed()
+        };
+        if is_reserved(name) {
+            panic!("SHEQ: id name is a reserved word, got {}", name);
+        }
+        let [Sexp::Sym(kw), Sexp::List(literals), rules @ ..] = spec.as_slice() else {
+            ill_formed()
+        };
+        if kw != "syntax-rules" {
+            ill_formed();
+        }
+        let literals = literals
+            .iter()
+            .map(|l| match l {
+                Sexp::Sym(l) => l.clone(),
+                _ => ill_formed(),
+            })
+            .collect::<Vec<_>>();
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                Sexp::List(parts) => match parts.as_slice() {
+                    [pat @ Sexp::List(_), template] => {
+                        let mut vars = Vec::new();
+                        pattern_vars(pat, &literals, true, &mut vars);
+                        for (i, var) in vars.iter().enumerate() {
+                            if vars[..i].contains(var) {
+                                panic!(
+                                    "SHEQ: duplicate pattern variable {} in {}",
+                                    var,
+                                    unparse_sexp(rule)
+                                );
+                            }
+                        }
+                        (pat.clone(), template.clone())
+                    }
+                    _ => panic!(
+                        "SHEQ: ill-formed syntax-rules clause, got {}",
+                        unparse_sexp(rule)
+                    ),
+                },
+                _ => panic!(
+                    "SHEQ: ill-formed syntax-rules clause, got {}",
+                    unparse_sexp(rule)
+                ),
+            })
+            .collect();
+        self.macros.push(SyntaxRules {
+            name: name.clone(),
+            literals,
+            rules,
+        });
+    }
+
+    fn lookup(&self, name: &str) -> Option<&SyntaxRules> {
+        self.macros.iter().rev().find(|m| m.name == name)
+    }
+
+    // fresh - a new fresh name for name
+    fn fresh(&mut self, name: &str) -> String {
+        self.renames += 1;
+        format!("{};{}", original(name), self.renames)
+    }
+
+    // resolve - the output name of a symbol: its binder's when it is bound, otherwise the symbol with
+    // any fresh name back to its original name, noting when a user binder would capture that name
+    fn resolve(&mut self, name: &str, bound: &Bound) -> String {
+        let out = resolve(name, bound);
+        let free_fresh = out != name && !bound.iter().any(|(from, _)| from == name);
+        if free_fresh && bound.iter().any(|(_, to)| *to == out) {
+            self.captured.push(out.clone());
+        }
+        out
+    }
+
+    // scoped - runs f on the forms in the scope of binders, returning each binder's output name and
+    // f's result. A user binder that would capture a free template name inside it gets a fresh name,
+    // and f runs again so the user's references follow it
+    fn scoped<R>(
+        &mut self,
+        bound: &Bound,
+        binders: &[String],
+        mut f: impl FnMut(&mut Self, &Bound) -> R,
+    ) -> (HashMap<String, String>, R) {
+        let outer = mem::take(&mut self.captured);
+        let mut names: HashMap<String, String> =
+            binders.iter().map(|b| (b.clone(), b.clone())).collect();
+        loop {
+            let mut inner = bound.to_vec();
+            inner.extend(binders.iter().map(|b| (b.clone(), names[b].clone())));
+            let result = f(self, &inner);
+            let captured = mem::take(&mut self.captured);
+            let clashes: Vec<String> = binders
+                .iter()
+                .filter(|b| captured.contains(&names[*b]))
+                .cloned()
+                .collect();
+            if clashes.is_empty() {
+                self.captured = outer;
+                self.captured.extend(captured);
+                return (names, result);
+            }
+            for b in clashes {
+                let renamed = self.fresh(&b);
+                names.insert(b, renamed);
+            }
+        }
+    }
+
+    // walk - expands the macro uses in s, bound holds the binders around it.
+    // Fresh names not bound go back to their original names on the way
+    fn walk(&mut self, s: &Sexp, bound: &Bound) -> Sexp {
+        let items = match s {
+            Sexp::Sym(name) => return Sexp::Sym(self.resolve(name, bound)),
+            Sexp::List(items) => items,
+            other => return other.clone(),
+        };
+        let Some(Sexp::Sym(h)) = items.first() else {
+            return Sexp::List(items.iter().map(|i| self.walk(i, bound)).collect());
+        };
+        // a local binding shadows a macro of the same name
+        if !bound.iter().any(|(from, _)| from == h) && self.lookup(&original(h)).is_some() {
+            return self.walk_use(&original(h), s, bound);
+        }
+        let head = self.resolve(h, bound);
+        let kw = Sexp::Sym(head.clone());
+        match (head.as_str(), &items[1..]) {
+            ("quote", rest) => {
+                let mut out = vec![kw];
+                out.extend(rest.iter().map(strip));
+                Sexp::List(out)
+            }
+            ("quasiquote", [template]) => {
+                Sexp::List(vec![kw, self.walk_template(template, 1, bound)])
+            }
+            ("define-struct", rest) => {
+                let mut out = vec![kw];
+                out.extend(rest.iter().map(strip));
+                Sexp::List(out)
+            }
+            ("lambda", [Sexp::List(params), body]) => {
+                let (names, body) = self.scoped(bound, &symbols(params), |this, inner| {
+                    this.walk(body, inner)
+                });
+                Sexp::List(vec![kw, Sexp::List(rename(params, &names)), body])
+            }
+            ("define", [Sexp::Sym(name), rhs]) => {
+                Sexp::List(vec![kw, Sexp::Sym(original(name)), self.walk(rhs, bound)])
+            }
+            ("define", [Sexp::List(header), body])
+                if matches!(header.first(), Some(Sexp::Sym(_))) =>
+            {
+                let (names, body) = self.scoped(bound, &symbols(&header[1..]), |this, inner| {
+                    this.walk(body, inner)
+                });
+                let mut header = rename(header, &names);
+                header[0] = strip(&header[0]);
+                Sexp::List(vec![kw, Sexp::List(header), body])
+            }
+            ("let", [clauses @ .., Sexp::Sym(kw_in), body, Sexp::Sym(kw_end)])
+                if original(kw_in) == "in" && original(kw_end) == "end" =>
+            {
+                // the values are outside the scope of the names, a clause binds None when ill-formed
+                let mut names = Vec::new();
+                let clauses: Vec<(Option<&Sexp>, Sexp)> = clauses
+                    .iter()
+                    .map(|clause| match clause {
+                        Sexp::List(parts) => match parts.as_slice() {
+                            [name, Sexp::Sym(eq), val] if original(eq) == "=" => {
+                                names.extend(symbols(std::slice::from_ref(name)));
+                                (Some(name), self.walk(val, bound))
+                            }
+                            _ => (None, self.walk(clause, bound)),
+                        },
+                        _ => (None, self.walk(clause, bound)),
+                    })
+                    .collect();
+                let (renamed, body) =
+                    self.scoped(bound, &names, |this, inner| this.walk(body, inner));
+                let mut out = vec![kw];
+                out.extend(clauses.into_iter().map(|(name, val)| match name {
+                    Some(name) => Sexp::List(vec![
+                        rename(std::slice::from_ref(name), &renamed).remove(0),
+                        Sexp::Sym("=".into()),
+                        val,
+                    ]),
+                    None => val,
+                }));
+                out.push(Sexp::Sym("in".into()));
+                out.push(body);
+                out.push(Sexp::Sym("end".into()));
+                Sexp::List(out)
+            }
+            ("match", [v, clauses @ ..]) => {
+                let mut out = vec![kw, self.walk(v, bound)];
+                for clause in clauses {
+                    out.push(match clause {
+                        Sexp::List(parts) if matches!(parts.len(), 2 | 4) => {
+                            let mut ids = Vec::new();
+                            walk_pattern(&parts[0], bound, &mut ids, &HashMap::new());
+                            let (names, rest) = self.scoped(bound, &ids, |this, inner| {
+                                parts[1..]
+                                    .iter()
+                                    .map(|p| this.walk(p, inner))
+                                    .collect::<Vec<_>>()
+                            });
+                            let mut clause = vec![walk_pattern(&parts[0], bound, &mut ids, &names)];
+                            clause.extend(rest);
+                            Sexp::List(clause)
+                        }
+                        _ => self.walk(clause, bound),
+                    });
+                }
+                Sexp::List(out)
+            }
+            _ => {
+                let mut out = vec![kw];
+                out.extend(items[1..].iter().map(|i| self.walk(i, bound)));
+                Sexp::List(out)
+            }
+        }
+    }
+
+    // walk_use - the marked expansion of s, a use of the macro name, with the macro uses in it expanded
+    fn walk_use(&mut self, name: &str, s: &Sexp, bound: &Bound) -> Sexp {
+        if self.depth >= MAX_EXPANSION_DEPTH {
+            panic!(
+                "SHEQ: macro expansion deeper than {} uses in {}",
+                MAX_EXPANSION_DEPTH,
+                unparse_sexp(&strip(s))
+            );
+        }
+        let expansion = self.expand_use(name, s);
+        self.depth += 1;
+        let out = self.walk(&expansion, bound);
+        self.depth -= 1;
+        marked(s, out)
+    }
+
+    // walk_template - walks a quasiquote template, only the unquotes of the outermost quasiquote are code
+    fn walk_template(&mut self, s: &Sexp, depth: usize, bound: &Bound) -> Sexp {
+        let Sexp::List(items) = s else {
+            return strip(s);
+        };
+        let depth = match items.as_slice() {
+            [Sexp::Sym(kw), e] if original(kw) == "unquote" => {
+                if depth == 1 {
+                    return Sexp::List(vec![Sexp::Sym("unquote".into()), self.walk(e, bound)]);
+                }
+                depth - 1
+            }
+            [Sexp::Sym(kw), _] if original(kw) == "quasiquote" => depth + 1,
+            _ => depth,
+        };
+        Sexp::List(
+            items
+                .iter()
+                .map(|i| self.walk_template(i, depth, bound))
+                .collect(),
+        )
+    }
+
+    // expand_use - one expansion of a use of the macro name, by the first rule whose pattern matches
+    fn expand_use(&mut self, name: &str, use_form: &Sexp) -> Sexp {
+        let m = self
+            .lookup(name)
+            .expect("SHEQ: expanding an undefined macro")
+            .clone();
+        let Sexp::List(form) = use_form else {
+            panic!(
+                "SHEQ: a macro use must be a list, got {}",
+                unparse_sexp(use_form)
+            )
+        };
+        for (pat, template) in &m.rules {
+            let Sexp::List(pats) = pat else {
+                panic!(
+                    "SHEQ: syntax-rules pattern must be a list, got {}",
+                    unparse_sexp(pat)
+                )
+            };
+            let mut binds = Bindings::new();
+            if match_list(&pats[1..], &form[1..], &m.literals, &mut binds) {
+                let mut fresh = HashMap::new();
+                return self.instantiate(template, &binds, &mut fresh, use_form);
+            }
+        }
+        panic!(
+            "SHEQ: no syntax-rules pattern of {} matched {}",
+            name,
+            unparse_sexp(&strip(use_form))
+        )
+    }
+
+    // instantiate - the template with its pattern variables replaced by what they matched,
+    // and every other symbol renamed to a fresh name, the same one each time within this expansion
+    fn instantiate(
+        &mut self,
+        t: &Sexp,
+        binds: &Bindings,
+        fresh: &mut HashMap<String, String>,
+        use_form: &Sexp,
+    ) -> Sexp {
+        match t {
+            Sexp::Sym(name) => match binds.get(name) {
+                Some(Match::One(s)) => s.clone(),
+                Some(Match::Many(_)) => panic!(
+                    "SHEQ: pattern variable {} needs an ellipsis in the expansion of {}",
+                    name,
+                    unparse_sexp(&strip(use_form))
+                ),
+                None => match fresh.get(name) {
+                    Some(renamed) => Sexp::Sym(renamed.clone()),
+                    None => {
+                        let renamed = self.fresh(name);
+                        fresh.insert(name.clone(), renamed.clone());
+                        Sexp::Sym(renamed)
+                    }
+                },
+            },
+            Sexp::List(items) => {
+                let mut out = Vec::new();
+                let mut i = 0;
+                while i < items.len() {
+                    if !matches!(items.get(i + 1), Some(Sexp::Sym(e)) if e == ELLIPSIS) {
+                        out.push(self.instantiate(&items[i], binds, fresh, use_form));
+                        i += 1;
+                        continue;
+                    }
+                    // the variables of items[i] that matched under an ellipsis are repeated together
+                    let mut vars = Vec::new();
+                    pattern_vars(&items[i], &[], false, &mut vars);
+                    let repeated: Vec<(&String, &Vec<Match>)> = vars
+                        .iter()
+                        .filter_map(|v| match binds.get(v) {
+                            Some(Match::Many(ms)) => Some((v, ms)),
+                            _ => None,
+                        })
+                        .collect();
+                    let Some((_, first)) = repeated.first() else {
+                        panic!(
+                            "SHEQ: ellipsis follows no repeated pattern variable in the expansion of {}",
+                            unparse_sexp(&strip(use_form))
+                        )
+                    };
+                    if repeated.iter().any(|(_, ms)| ms.len() != first.len()) {
+                        panic!(
+                            "SHEQ: repeated pattern variables matched different numbers of forms in {}",
+                            unparse_sexp(&strip(use_form))
+                        );
+                    }
+                    for k in 0..first.len() {
+                        let mut inner = binds.clone();
+                        for (v, ms) in &repeated {
+                            inner.insert((*v).clone(), ms[k].clone());
+                        }
+                        out.push(self.instantiate(&items[i], &inner, fresh, use_form));
+                    }
+                    i += 2;
+                }
+                Sexp::List(out)
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+// marked - the expansion of use_form, marked with the use as written
+fn marked(use_form: &Sexp, expansion: Sexp) -> Sexp {
+    Sexp::List(vec![
+        Sexp::Sym(EXPANSION.into()),
+        Sexp::Str(unparse_sexp(&strip(use_form))),
+        expansion,
+    ])
+}
+
+// expansion_of - the use and the expansion of a marked expansion, None for any other form
+pub(crate) fn expansion_of(s: &Sexp) -> Option<(&str, &Sexp)> {
+    match s {
+        Sexp::List(items) => match items.as_slice() {
+            [Sexp::Sym(kw), Sexp::Str(site), expansion] if kw == EXPANSION => {
+                Some((site, expansion))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// at_use_site - runs f, which parses or evaluates the expansion of the use site, adding site to
+// the message of a SHEQ error raised inside it that no inner expansion has named its use in yet
+pub(crate) fn at_use_site<T>(site: &str, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<String>() {
+            Some(msg) => Some(msg.as_str()),
+            None => payload.downcast_ref::<&str>().copied(),
+        };
+        match message {
+            Some(msg) if msg.starts_with("SHEQ:") && !msg.contains(USE_SITE) => {
+                panic::resume_unwind(Box::new(format!("{}{}{}", msg, USE_SITE, site)))
+            }
+            _ => panic::resume_unwind(payload),
+        }
+    })
+}
+
+// match_syntax - whether form matches pat, adding what each pattern variable matched to binds
+fn match_syntax(pat: &Sexp, form: &Sexp, literals: &[String], binds: &mut Bindings) -> bool {
+    match (pat, form) {
+        (Sexp::Sym(p), _) if p == "_" => true,
+        (Sexp::Sym(p), _) if literals.contains(p) => {
+            matches!(form, Sexp::Sym(f) if original(f) == *p)
+        }
+        (Sexp::Sym(p), _) => {
+            binds.insert(p.clone(), Match::One(form.clone()));
+            true
+        }
+        (Sexp::List(pats), Sexp::List(forms)) => match_list(pats, forms, literals, binds),
+        (Sexp::List(_), _) => false,
+        _ => pat == form,
+    }
+}
+
+// match_list - matches a list of forms against a list of patterns, at most one of which has an ellipsis
+fn match_list(pats: &[Sexp], forms: &[Sexp], literals: &[String], binds: &mut Bindings) -> bool {
+    let ellipsis = pats
+        .iter()
+        .position(|p| matches!(p, Sexp::Sym(e) if e == ELLIPSIS));
+    let Some(at) = ellipsis.filter(|at| *at > 0) else {
+        return pats.len() == forms.len()
+            && pats
+                .iter()
+                .zip(forms)
+                .all(|(p, f)| match_syntax(p, f, literals, binds));
+    };
+    let (before, repeated, after) = (&pats[..at - 1], &pats[at - 1], &pats[at + 1..]);
+    if forms.len() < before.len() + after.len() {
+        return false;
+    }
+    let rest = forms.len() - after.len();
+    if !match_list(before, &forms[..before.len()], literals, binds)
+        || !match_list(after, &forms[rest..], literals, binds)
+    {
+        return false;
+    }
+    let mut runs = Vec::new();
+    for form in &forms[before.len()..rest] {
+        let mut run = Bindings::new();
+        if !match_syntax(repeated, form, literals, &mut run) {
+            return false;
+        }
+        runs.push(run);
+    }
+    let mut vars = Vec::new();
+    pattern_vars(repeated, literals, false, &mut vars);
+    for var in vars {
+        let ms = runs
+            .iter_mut()
+            .map(|run| {
+                run.remove(&var)
+                    .expect("SHEQ: every run binds the same variables")
+            })
+            .collect();
+        binds.insert(var, Match::Many(ms));
+    }
+    true
+}
+
+// pattern_vars - the pattern variables of pat, skip_head leaves out the macro keyword of a whole pattern
+fn pattern_vars(pat: &Sexp, literals: &[String], skip_head: bool, acc: &mut Vec<String>) {
+    match pat {
+        Sexp::Sym(p) if p != "_" && p != ELLIPSIS && !literals.contains(p) => acc.push(p.clone()),
+        Sexp::List(items) => {
+            for item in &items[usize::from(skip_head).min(items.len())..] {
+                pattern_vars(item, literals, false, acc);
+            }
+        }
+        _ => {}
+    }
+}
+
+// walk_pattern - a match pattern, pushing the ids it binds onto ids and outputting them as named.
+// Literals, list, cons and struct names are resolved like any free name
+fn walk_pattern(
+    p: &Sexp,
+    bound: &Bound,
+    ids: &mut Vec<String>,
+    names: &HashMap<String, String>,
+) -> Sexp {
+    match p {
+        Sexp::Sym(name) => match original(name).as_str() {
+            "_" | "true" | "false" | "empty" => Sexp::Sym(original(name)),
+            _ => {
+                ids.push(name.clone());
+                Sexp::Sym(names.get(name).unwrap_or(name).clone())
+            }
+        },
+        Sexp::List(items) => match items.first() {
+            Some(Sexp::Sym(head)) => {
+                let head = resolve(head, bound);
+                if head == "quote" {
+                    return strip(p);
+                }
+                let mut out = vec![Sexp::Sym(head)];
+                out.extend(
+                    items[1..]
+                        .iter()
+                        .map(|i| walk_pattern(i, bound, ids, names)),
+                );
+                Sexp::List(out)
+            }
+            _ => Sexp::List(
+                items
+                    .iter()
+                    .map(|i| walk_pattern(i, bound, ids, names))
+                    .collect(),
+            ),
+        },
+        other => other.clone(),
+    }
+}
+
+// original - the name a fresh name was made from, other names are their own
+fn original(name: &str) -> String {
+    match name.split_once(';') {
+        Some((orig, _)) => orig.to_string(),
+        None => name.to_string(),
+    }
+}
+
+// resolve - a bound name is its binder's output name, a fresh name not bound goes back to its original name
+fn resolve(name: &str, bound: &Bound) -> String {
+    match bound.iter().rev().find(|(from, _)| from == name) {
+        Some((_, to)) => to.clone(),
+        None => original(name),
+    }
+}
+
+// strip - every fresh name in s back to its original name, for quoted data and error messages
+fn strip(s: &Sexp) -> Sexp {
+    match s {
+        Sexp::Sym(name) => Sexp::Sym(original(name)),
+        Sexp::List(items) => Sexp::List(items.iter().map(strip).collect()),
+        other => other.clone(),
+    }
+}
+
+// symbols - the symbols among forms, the names a binding form binds
+fn symbols(forms: &[Sexp]) -> Vec<String> {
+    forms
+        .iter()
+        .filter_map(|f| match f {
+            Sexp::Sym(name) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// rename - forms with each symbol given its output name from names
+fn rename(forms: &[Sexp], names: &HashMap<String, String>) -> Vec<Sexp> {
+    forms
+        .iter()
+        .map(|f| match f {
+            Sexp::Sym(name) => Sexp::Sym(names.get(name).unwrap_or(name).clone()),
+            other => other.clone(),
+        })
+        .collect()
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::read;
+    use crate::{Config, top_interp_program};
+
+    fn run(src: &str) -> Vec<String> {
+        top_interp_program(src, Config::default())
+    }
+
+    const MY_OR: &str = "{define-syntax my-or
+                           {syntax-rules ()
+                             [(_) false]
+                             [(_ e) e]
+                             [(_ e r ...) {let [t = e] in {if t t {my-or r ...}} end}]}}";
+
+    #[test]
+    fn expand_renames_introduced_names() {
+        let mut macros = Macros::new();
+        let def = read("{define-syntax adder {syntax-rules () [(_ e) {lambda (x) {+ x e}}]}}");
+        assert_eq!(macros.expand_top(&def), None);
+        let expanded = macros.expand(&read("{adder x}"));
+        assert_eq!(unparse_sexp(&expanded), "{lambda {x;2} {+ x;2 x}}");
+    }
+
+    #[test]
+    fn recursive_macro_with_ellipsis() {
+        let src = format!("{} {{my-or false {{< 2 1}} \"yes\"}} {{my-or}}", MY_OR);
+        assert_eq!(run(&src), vec!["\"yes\"", "false"]);
+    }
+
+    #[test]
+    fn introduced_binders_do_not_capture() {
+        let src = format!("{} {{let [t = 5] in {{my-or false t}} end}}", MY_OR);
+        assert_eq!(run(&src), vec!["5"]);
+        let src = "{define-syntax first-or-zero
+                     {syntax-rules () [(_ e) {match e [{cons h t} h] [_ 0]}]}}
+                   {let [h = 9] in {first-or-zero {list h}} end}";
+        assert_eq!(run(src), vec!["9"]);
+    }
+
+    #[test]
+    fn literals_and_nested_ellipsis_patterns() {
+        let src = "{define-syntax my-cond
+                     {syntax-rules (else)
+                       [(_ [else e]) e]
+                       [(_ [c e] rest ...) {if c e {my-cond rest ...}}]}}
+                   {define-syntax sums {syntax-rules () [(_ (a b) ...) {list {+ a b} ...}]}}
+                   {my-cond [{< 2 1} \"a\"] [false \"b\"] [else \"c\"]}
+                   {sums (1 2) (3 4)}";
+        assert_eq!(run(src), vec!["\"c\"", "'(3 7)"]);
+    }
+
+    #[test]
+    fn templates_see_top_level_names() {
+        let src = "{define {double x} {* 2 x}}
+                   {define-syntax twice {syntax-rules () [(_ e) {double e}]}}
+                   {define-syntax tag {syntax-rules () [(_ e) '(e tagged)]}}
+                   {twice 4}
+                   {tag {+ 1 2}}";
+        assert_eq!(run(src), vec!["8", "'((+ 1 2) tagged)"]);
+    }
+
+    const INC: &str = "{define-syntax inc {syntax-rules () [(_ a) {+ a 1}]}}";
+
+    #[test]
+    fn use_site_binders_do_not_capture_template_names() {
+        let src = format!(
+            "{} {{{{lambda (+) {{inc 2}}}} -}}
+               {{{{lambda (+) {{+ {{inc 2}} 1}}}} -}}
+               {{let [+ = -] in {{inc 2}} end}}
+               {{match - [+ {{inc 2}}]}}
+               {{define {{f +}} {{+ {{inc 2}} 10}}}}
+               {{f -}}",
+            INC
+        );
+        assert_eq!(run(&src), vec!["3", "2", "3", "3", "-7"]);
+        let src = "{define {double x} {* 2 x}}
+                   {define-syntax twice {syntax-rules () [(_ e) {double e}]}}
+                   {{lambda (double) {twice 4}} 0}";
+        assert_eq!(run(src), vec!["8"]);
+    }
+
+    #[test]
+    fn capturing_user_binder_is_renamed() {
+        let mut macros = Macros::new();
+        assert_eq!(macros.expand_top(&read(INC)), None);
+        let expanded = macros.expand(&read("{lambda (+ x) {+ {inc x} x}}"));
+        assert_eq!(unparse_sexp(&expanded), "{lambda {+;2 x} {+;2 {+ x 1} x}}");
+    }
+
+    #[test]
+    fn template_binders_do_not_capture_use_site_names() {
+        let src = "{define-syntax with-x {syntax-rules () [(_ e) {let [x = 1] in {+ x e} end}]}}
+                   {let [x = 5] in {with-x x} end}";
+        assert_eq!(run(src), vec!["6"]);
+    }
+
+    #[test]
+    fn deep_recursive_expansion() {
+        let args = vec!["false"; 100].join(" ");
+        let src = format!("{} {{my-or {} \"last\"}}", MY_OR, args);
+        assert_eq!(run(&src), vec!["\"last\""]);
+    }
+
+    #[test]
+    #[should_panic(expected = "macro expansion deeper than 250 uses in {loop}")]
+    fn endless_expansion() {
+        let _ = run("{define-syntax loop {syntax-rules () [(_) {loop}]}} {loop}");
+    }
+
+    #[test]
+    fn local_binding_shadows_macro() {
+        let src = format!(
+            "{} {{let [my-or = {{lambda (a b) \"fn\"}}] in {{my-or 1 2}} end}}",
+            MY_OR
+        );
+        assert_eq!(run(&src), vec!["\"fn\""]);
+    }
+
+    #[test]
+    #[should_panic(expected = "no syntax-rules pattern of my-cond matched {my-cond 1}")]
+    fn no_pattern_matches_use() {
+        let _ = run(
+            "{define-syntax my-cond {syntax-rules () [(_ [c e]) {if c e false}]}}
+                     {my-cond 1}",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "pattern variable r needs an ellipsis in the expansion of {bad 1 2}")]
+    fn template_missing_ellipsis() {
+        let _ = run("{define-syntax bad {syntax-rules () [(_ r ...) {list r}]}} {bad 1 2}");
+    }
+
+    const HEAD: &str = "{define-syntax head {syntax-rules () [(_ e) {first e}]}}";
+
+    #[test]
+    #[should_panic(expected = ", in the expansion of {head empty}")]
+    fn runtime_error_names_use_site() {
+        let _ = run(&format!("{} {{+ 1 {{head empty}}}}", HEAD));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "SHEQ: ill-formed if, got {if true}, in the expansion of {bad-if true}"
+    )]
+    fn parse_error_names_use_site() {
+        let _ = run("{define-syntax bad-if {syntax-rules () [(_ c) {if c}]}} {bad-if true}");
+    }
+
+    #[test]
+    fn errors_name_innermost_use_site() {
+        let mut sheq = crate::Interpreter::new();
+        let def_bad = "{define-syntax def-bad {syntax-rules () [(_ n) {define n {/ 1 0}}]}}";
+        sheq.eval_program(&format!("{} {} {}", MY_OR, HEAD, def_bad))
+            .unwrap();
+        let error = |src: &str| sheq.clone().eval_program(src).unwrap_err().message;
+        // the error in head's expansion is inside my-or's, only head's use is named
+        assert_eq!(
+            error("{my-or false {head {list}}}"),
+            format!(
+                "{}, in the expansion of {{head {{list}}}}",
+                error("{first {list}}")
+            )
+        );
+        assert_eq!(
+            error("{def-bad x}"),
+            "SHEQ: Divide by zero error, in the expansion of {def-bad x}"
+        );
+        // the code passed to a use is part of its expansion
+        assert_eq!(
+            error("{my-or {/ 1 0}}"),
+            "SHEQ: Divide by zero error, in the expansion of {my-or {/ 1 0}}"
+        );
+        assert_eq!(error("{/ 1 0}"), "SHEQ: Divide by zero error");
+    }
+
+    #[test]
+    fn stepping_an_expansion_keeps_its_use_site() {
+        let mut macros = Macros::new();
+        assert_eq!(macros.expand_top(&read(HEAD)), None);
+        let e = crate::parse::parse(&macros.expand(&read("{head {rest {list 1 2}}}")));
+        let steps = crate::step::steps(&e, &crate::base_env(Config::default()));
+        assert_eq!(
+            steps.iter().map(crate::parse::unparse).collect::<Vec<_>>(),
+            vec![
+                "{first {rest {list 1 2}}}",
+                "{first {rest '(1 2)}}",
+                "{first '(2)}",
+                "2"
+            ]
+        );
+        assert!(steps.iter().all(
+            |e| matches!(e, crate::ExprC::ExpansionC(e) if e.site == "{head {rest {list 1 2}}}")
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "ill-formed define-syntax")]
+    fn define_syntax_needs_syntax_rules() {
+        let _ = run("{define-syntax m {lambda (x) x}}");
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate pattern variable a")]
+    fn duplicate_pattern_variable() {
+        let _ = run("{define-syntax m {syntax-rules () [(_ a a) a]}}");
+    }
+}
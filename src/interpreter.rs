@@ -0,0 +1,468 @@
+// Interpreter - an embeddable SHEQ interpreter that owns its global Env.
+// Globals start as base_env(), and every call on the same instance sees the globals defined before it.
+// The globals own the definitions, a procedure an Interpreter returns can only use the definitions
+// of its program while the Interpreter is alive.
+// A SHEQ error, which the evaluators raise as a panic, is caught and returned as a SheqError.
+
+use crate::expand::Macros;
+use crate::module::Modules;
+use crate::parse::{parse, parse_program_with, read};
+use crate::step::steps;
+use crate::{
+    Arity, Binding, Config, Env, ExprC, HostFn, HostV, Scope, Strategy, TopLevel, Value, base_env,
+    force, interp_with,
+};
+use std::any::Any;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+// SheqError - an error raised while evaluating, message is the panic's, e.g. "SHEQ: unbound identifier 'x'"
+#[derive(Debug, Clone, PartialEq)]
+pub struct SheqError {
+    pub message: String,
+}
+
+impl fmt::Display for SheqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SheqError {}
+
+// catch - runs f, returning a panic inside it as a SheqError. While f runs, the panic hook leaves out
+// the report of a SHEQ error on this thread, which the caller gets as the Err instead; any other panic,
+// like an internal bug, is still reported by the hook that was installed before, which is put back after
+fn catch<T>(f: impl FnOnce() -> T) -> Result<T, SheqError> {
+    enter_catch();
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    leave_catch();
+    result.map_err(|payload| SheqError {
+        message: match payload.downcast::<String>() {
+            Ok(msg) => *msg,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(msg) => msg.to_string(),
+                Err(_) => "SHEQ: evaluation panicked".to_string(),
+            },
+        },
+    })
+}
+
+type PanicHook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+// SILENCER - how many catches are running on any thread, and the hook that was installed before the
+// first of them, which the silencing hook delegates to and which the last of them puts back
+static SILENCER: Mutex<(usize, Option<Arc<PanicHook>>)> = Mutex::new((0, None));
+
+thread_local! {
+    // CATCHING - how many catches are running on this thread
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+}
+
+// enter_catch - silences SHEQ panics on this thread, installing the silencing hook if no catch is running
+fn enter_catch() {
+    let mut silencer = SILENCER.lock().unwrap_or_else(|e| e.into_inner());
+    if silencer.0 == 0 {
+        let previous: Arc<PanicHook> = Arc::new(panic::take_hook());
+        let outer = previous.clone();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) == 0 || sheq_message(info.payload()).is_none() {
+                outer(info);
+            }
+        }));
+        silencer.1 = Some(previous);
+    }
+    silencer.0 += 1;
+    CATCHING.with(|c| c.set(c.get() + 1));
+}
+
+// leave_catch - undoes enter_catch, putting the previous hook back once no catch is running
+fn leave_catch() {
+    CATCHING.with(|c| c.set(c.get() - 1));
+    let mut silencer = SILENCER.lock().unwrap_or_else(|e| e.into_inner());
+    silencer.0 -= 1;
+    if silencer.0 == 0 {
+        // dropping the silencing hook drops its clone of previous, so previous can be unwrapped
+        drop(panic::take_hook());
+        if let Some(previous) = silencer.1.take().and_then(|p| Arc::try_unwrap(p).ok()) {
+            panic::set_hook(previous);
+        }
+    }
+}
+
+// sheq_message - the message of a panic payload raised as a SHEQ error
+fn sheq_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    let message = match payload.downcast_ref::<String>() {
+        Some(msg) => msg.as_str(),
+        None => *payload.downcast_ref::<&str>()?,
+    };
+    message.starts_with("SHEQ:").then_some(message)
+}
+
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    globals: Env,
+    // the globals the host provided: the initial env and every define_global, modules start from these
+    base: Env,
+    config: Config,
+    macros: Macros,
+    // modules required so far, each is evaluated once per Interpreter
+    modules: Modules,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    // new - an Interpreter whose globals are base_env(), evaluating strictly with static scope
+    pub fn new() -> Self {
+        Interpreter::with_config(Config::default())
+    }
+
+    // with_config - an Interpreter using the given evaluation options, its globals include the
+    // prelude only if config.prelude
+    pub fn with_config(config: Config) -> Self {
+        let globals = base_env(config);
+        Interpreter {
+            base: globals.clone(),
+            globals,
+            config,
+            macros: Macros::new(),
+            modules: Modules::new(),
+        }
+    }
+
+    // with_env - an Interpreter whose globals start as env, e.g. Registry::env
+    pub fn with_env(env: Env) -> Self {
+        Interpreter {
+            base: env.clone(),
+            globals: env,
+            config: Config::default(),
+            macros: Macros::new(),
+            modules: Modules::new(),
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    // set_config - the options for later evaluations, the globals (and so any prelude) stay as they are
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    // globals - the current global Env, most recent definitions last
+    pub fn globals(&self) -> &Env {
+        &self.globals
+    }
+
+    // eval_str - evaluates a SHEQ program in the globals and returns the value of its last expression,
+    // its definitions become globals
+    pub fn eval_str(&mut self, src: &str) -> Result<Value, SheqError> {
+        match self.eval_program(src)?.pop() {
+            Some(v) => Ok(v),
+            None => Err(SheqError {
+                message: format!("SHEQ: expected an expression to evaluate in {:?}", src),
+            }),
+        }
+    }
+
+    // eval_program - evaluates a SHEQ program in the globals, returning the value of each expression.
+    // Its definitions, macros and imports become globals only if the whole program runs without error,
+    // its requires are relative to the current directory
+    pub fn eval_program(&mut self, src: &str) -> Result<Vec<Value>, SheqError> {
+        let mut macros = self.macros.clone();
+        let mut globals = self.globals.clone();
+        let mut modules = self.modules.clone();
+        let results = catch(|| {
+            let forms: Vec<TopLevel> = parse_program_with(src, &mut macros);
+            modules.run_program(
+                &forms,
+                Path::new("."),
+                &mut globals,
+                &self.base,
+                self.config,
+            )
+        })?;
+        self.globals = globals;
+        self.macros = macros;
+        self.modules = modules;
+        Ok(results)
+    }
+
+    // eval_expr - evaluates an ExprC in the globals, the result is always forced
+    pub fn eval_expr(&mut self, e: &ExprC) -> Result<Value, SheqError> {
+        catch(|| force(interp_with(e, &self.globals, self.config)))
+    }
+
+    // steps - every intermediate program of the reduction of the expression in src, starting with the
+    // expression itself, in the globals and with the macros defined so far. The stepper shows strict
+    // evaluation with static scope, so it refuses to step under any other config
+    pub fn steps(&self, src: &str) -> Result<Vec<ExprC>, SheqError> {
+        if self.config.strategy != Strategy::Strict || self.config.scope != Scope::Static {
+            return Err(SheqError {
+                message: format!(
+                    "SHEQ: the stepper only shows strict evaluation with static scope, got {:?} with {:?} scope",
+                    self.config.strategy, self.config.scope
+                ),
+            });
+        }
+        let mut macros = self.macros.clone();
+        catch(|| match macros.expand_top(&read(src)) {
+            Some(form) => steps(&parse(&form), &self.globals),
+            None => panic!("SHEQ: expected an expression to step in {:?}", src),
+        })
+    }
+
+    // define_global - binds name to v for every later evaluation, shadowing any earlier binding,
+    // and in every module first required after it
+    pub fn define_global(&mut self, name: &str, v: Value) {
+        let binding = Binding {
+            name: name.into(),
+            val: Box::new(v),
+        };
+        self.base.push(binding.clone());
+        self.globals.push(binding);
+    }
+
+    // get_global - the current value of a global, if it is bound
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals
+            .iter()
+            .rev()
+            .find(|b| b.name == name)
+            .map(|b| force((*b.val).clone()))
+    }
+
+    // register - defines a host function global, see Registry::register
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        func: impl Fn(Vec<Value>) -> Value + 'static,
+    ) -> &mut Self {
+        self.define_global(
+            name,
+            Value::HostV(HostV {
+                name: name.into(),
+                arity,
+                func: Rc::new(func),
+            }),
+        );
+        self
+    }
+
+    // register_fn - defines a typed host function global, see Registry::register_fn
+    pub fn register_fn<Args>(
+        &mut self,
+        name: &str,
+        func: impl HostFn<Args> + 'static,
+    ) -> &mut Self {
+        let arity = Arity::Exact(func.arity());
+        let owned = name.to_string();
+        self.register(name, arity, move |args| func.call(&owned, args))
+    }
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Rational, serialize};
+
+    #[test]
+    fn catch_silences_only_sheq_panics() {
+        let err = catch(|| panic!("SHEQ: boom")).unwrap_err();
+        assert_eq!(err.message, "SHEQ: boom");
+        assert_eq!(CATCHING.with(Cell::get), 0);
+        assert_eq!(sheq_message(&"SHEQ: boom".to_string()), Some("SHEQ: boom"));
+        assert_eq!(sheq_message(&"SHEQ: boom"), Some("SHEQ: boom"));
+        assert_eq!(sheq_message(&"index out of bounds".to_string()), None);
+        assert_eq!(sheq_message(&7), None);
+    }
+
+    #[test]
+    fn globals_persist_across_calls() {
+        let mut sheq = Interpreter::new();
+        sheq.define_global("port", Value::Real(8080.0));
+        let double = sheq.eval_str("{lambda (x) {* 2 x}}").unwrap();
+        sheq.define_global("double", double);
+        assert_eq!(sheq.eval_str("{double port}"), Ok(Value::Real(16160.0)));
+        assert_eq!(sheq.get_global("port"), Some(Value::Real(8080.0)));
+        assert_eq!(sheq.get_global("missing"), None);
+    }
+
+    #[test]
+    fn program_definitions_persist() {
+        let mut sheq = Interpreter::new();
+        assert_eq!(
+            sheq.eval_program("{define base 10} {define {scale n} {* base n}}"),
+            Ok(vec![])
+        );
+        assert_eq!(
+            sheq.eval_str("{scale 4}"),
+            Ok(Value::Exact(Rational::from(40)))
+        );
+        assert!(matches!(sheq.get_global("scale"), Some(Value::CloV(_))));
+    }
+
+    #[test]
+    fn failed_program_defines_nothing() {
+        let mut sheq = Interpreter::new();
+        assert_eq!(
+            sheq.eval_program("{define x 1} {error \"stop\"}"),
+            Err(SheqError {
+                message: "SHEQ: stop".into()
+            })
+        );
+        assert_eq!(sheq.get_global("x"), None);
+    }
+
+    #[test]
+    fn macros_persist() {
+        let mut sheq = Interpreter::new();
+        sheq.eval_program("{define-syntax swap {syntax-rules () [(_ f a b) {f b a}]}}")
+            .unwrap();
+        assert_eq!(
+            sheq.eval_str("{swap - 1 4}"),
+            Ok(Value::Exact(Rational::from(3)))
+        );
+        assert!(
+            sheq.eval_program("{define-syntax nope {syntax-rules () [(_) 1]}} {error \"stop\"}")
+                .is_err()
+        );
+        assert_eq!(
+            serialize(&sheq.eval_str("{lambda () {nope}}").unwrap()),
+            "#<procedure>"
+        );
+    }
+
+    #[test]
+    fn define_global_shadows() {
+        let mut sheq = Interpreter::new();
+        sheq.define_global("x", Value::Real(1.0));
+        sheq.define_global("x", Value::String("one".into()));
+        assert_eq!(serialize(&sheq.eval_str("x").unwrap()), "\"one\"");
+    }
+
+    #[test]
+    fn instances_are_independent() {
+        let mut a = Interpreter::new();
+        let b = Interpreter::new();
+        a.define_global("x", Value::Real(1.0));
+        assert_eq!(b.get_global("x"), None);
+    }
+
+    #[test]
+    fn register_host_fn() {
+        let mut sheq = Interpreter::new();
+        sheq.register("twice", Arity::Exact(1), |args| match args.as_slice() {
+            [Value::Exact(n)] => Value::Exact(n + n),
+            _ => panic!("SHEQ: twice expected a number, got {:?}", args),
+        });
+        assert_eq!(
+            sheq.eval_str("{twice 21}"),
+            Ok(Value::Exact(Rational::from(42)))
+        );
+        // a host function's panic is an error too
+        assert_eq!(
+            sheq.eval_str("{twice \"a\"}").unwrap_err().message,
+            "SHEQ: twice expected a number, got [String(\"a\")]"
+        );
+    }
+
+    #[test]
+    fn config_is_used() {
+        let mut sheq = Interpreter::with_config(Config {
+            scope: Scope::Dynamic,
+            ..Config::default()
+        });
+        let f = sheq.eval_str("{lambda () y}").unwrap();
+        sheq.define_global("f", f);
+        assert_eq!(
+            sheq.eval_str("{{lambda (y) {f}} 7.0}"),
+            Ok(Value::Real(7.0))
+        );
+    }
+
+    #[test]
+    fn steps_use_globals_and_macros() {
+        let mut sheq = Interpreter::new();
+        sheq.eval_program(
+            "{define {double x} {* 2 x}} {define-syntax twice {syntax-rules () [(_ e) {+ e e}]}}",
+        )
+        .unwrap();
+        sheq.register_fn("inc", |n: f64| n + 1.0);
+        let trace: Vec<String> = sheq
+            .steps("{twice {double {inc 1.0}}}")
+            .unwrap()
+            .iter()
+            .map(crate::parse::unparse)
+            .collect();
+        assert_eq!(
+            trace,
+            vec![
+                "{+ {double {inc 1.0}} {double {inc 1.0}}}",
+                "{+ {double 2.0} {double {inc 1.0}}}",
+                "{+ 4.0 {double {inc 1.0}}}",
+                "{+ 4.0 {double 2.0}}",
+                "{+ 4.0 4.0}",
+                "8.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_refuse_lazy() {
+        let sheq = Interpreter::with_config(Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        });
+        let err = sheq.steps("{+ 1 2}").unwrap_err();
+        assert!(
+            err.message
+                .starts_with("SHEQ: the stepper only shows strict evaluation with static scope"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn eval_str_errors() {
+        let mut sheq = Interpreter::new();
+        assert_eq!(
+            sheq.eval_str("nope"),
+            Err(SheqError {
+                message: "SHEQ: unbound identifier 'nope'".into()
+            })
+        );
+        assert_eq!(
+            sheq.eval_str("{define x 1}").unwrap_err().message,
+            "SHEQ: expected an expression to evaluate in \"{define x 1}\""
+        );
+        assert!(sheq.eval_program("{+ 1").is_err());
+        // the Interpreter is still usable after an error
+        assert_eq!(
+            sheq.eval_str("{+ 1 2}"),
+            Ok(Value::Exact(Rational::from(3)))
+        );
+    }
+
+    #[test]
+    fn eval_expr_errors() {
+        let mut sheq = Interpreter::new();
+        let e = crate::parse::parse(&read("{/ 1 0}"));
+        assert_eq!(
+            sheq.eval_expr(&e).unwrap_err().to_string(),
+            "SHEQ: Divide by zero error"
+        );
+    }
+}
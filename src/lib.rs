@@ -1,16 +1,262 @@
 // SHEQ4
 // WIP implementation of SHEQ4.
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::{Rc, Weak};
+
+pub mod convert;
+pub mod expand;
+pub mod interpreter;
+pub mod module;
+pub mod number;
+pub mod parse;
+pub mod step;
+pub mod subst;
+pub mod vm;
+
+pub use convert::{FromValue, HostFn, IntoValue};
+use expand::at_use_site;
+pub use interpreter::{Interpreter, SheqError};
+use module::Modules;
+pub use number::{BigInt, Rational};
+use parse::{Sexp, is_balanced, parse, parse_program, read, read_number, unparse};
+
 // Data definitions
 
-// Value - Numbers, Booleans, String, CloV, PrimV
-#[derive(Debug, Clone, PartialEq)]
+// Value - Numbers (inexact Real or Exact), Booleans, String, Symbol, Pair and Null lists, StructV records,
+// immutable Hash maps, CloV, PrimV, HostV, StructOp, Thunk, and Void for forms with no useful result
+#[derive(Debug, Clone)]
 pub enum Value {
     Real(f64),
+    Exact(Rational),
     Boolean(bool),
     String(String),
+    Symbol(String),
+    Pair(Rc<Pair>),
+    Null,
+    StructV(StructV),
+    Hash(Rc<HashMap<Value, Value>>),
     CloV(CloV),
     PrimV(PrimV),
+    HostV(HostV),
+    StructOp(StructOp),
+    Thunk(Thunk),
+    Void,
+}
+
+// Pair - a cons cell, lists are Pairs ending in Null, compared structurally
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub first: Value,
+    pub rest: Value,
+}
+
+// Pairs compare along the spine in a loop, recursing on every rest would overflow the stack on a long list
+impl PartialEq for Pair {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut a, mut b) = (self, other);
+        loop {
+            if std::ptr::eq(a, b) {
+                return true;
+            }
+            if a.first != b.first {
+                return false;
+            }
+            match (&a.rest, &b.rest) {
+                (Value::Pair(x), Value::Pair(y)) => (a, b) = (x, y),
+                (x, y) => return x == y,
+            }
+        }
+    }
+}
+
+// Dropping a Pair frees the rest of its list in a loop, for the same reason, stopping at a tail
+// that is still shared
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut rest = std::mem::replace(&mut self.rest, Value::Null);
+        while let Value::Pair(pair) = rest {
+            match Rc::try_unwrap(pair) {
+                Ok(mut pair) => rest = std::mem::replace(&mut pair.rest, Value::Null),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+// Values are equal as by equal?: Reals are equal as by eqv?, so +nan.0 equals itself and 0.0 is not -0.0,
+// an Exact never equals a Real, and closures are equal when their params, body and Env are
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Real(a), Value::Real(b)) => real_bits(*a) == real_bits(*b),
+            (Value::Exact(a), Value::Exact(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::Pair(a), Value::Pair(b)) => a == b,
+            (Value::Null, Value::Null) | (Value::Void, Value::Void) => true,
+            (Value::StructV(a), Value::StructV(b)) => a == b,
+            (Value::Hash(a), Value::Hash(b)) => a == b,
+            (Value::CloV(a), Value::CloV(b)) => a == b,
+            (Value::PrimV(a), Value::PrimV(b)) => a == b,
+            (Value::HostV(a), Value::HostV(b)) => a == b,
+            (Value::StructOp(a), Value::StructOp(b)) => a == b,
+            (Value::Thunk(a), Value::Thunk(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+// equal Values hash the same, procedures and thunks only hash their variant
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Real(n) => real_bits(*n).hash(state),
+            Value::Exact(n) => n.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::String(s) | Value::Symbol(s) => s.hash(state),
+            Value::Pair(pair) => {
+                pair.first.hash(state);
+                pair.rest.hash(state);
+            }
+            Value::StructV(sv) => {
+                sv.ty.name.hash(state);
+                sv.fields.hash(state);
+            }
+            // entries have no order, so only their number is hashed
+            Value::Hash(map) => map.len().hash(state),
+            Value::Null
+            | Value::Void
+            | Value::CloV(_)
+            | Value::PrimV(_)
+            | Value::HostV(_)
+            | Value::StructOp(_)
+            | Value::Thunk(_) => {}
+        }
+    }
+}
+
+// real_bits - the bits of n, with every NaN the same
+fn real_bits(n: f64) -> u64 {
+    if n.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        n.to_bits()
+    }
+}
+
+impl Value {
+    pub fn cons(first: Value, rest: Value) -> Value {
+        Value::Pair(Rc::new(Pair { first, rest }))
+    }
+
+    // list - a proper list of the items, in order
+    pub fn list(items: Vec<Value>) -> Value {
+        items
+            .into_iter()
+            .rev()
+            .fold(Value::Null, |rest, first| Value::cons(first, rest))
+    }
+
+    // list_items - the elements of a proper list, None if this is not one
+    pub fn list_items(&self) -> Option<Vec<Value>> {
+        let mut items = Vec::new();
+        let mut cur = self;
+        loop {
+            match cur {
+                Value::Null => return Some(items),
+                Value::Pair(pair) => {
+                    items.push(pair.first.clone());
+                    cur = &pair.rest;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+// StructType - a record type made by define-struct, every definition makes a distinct type
+#[derive(Debug, PartialEq)]
+pub struct StructType {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+// StructV - an instance of a StructType, holding one Value per field
+#[derive(Debug, Clone)]
+pub struct StructV {
+    pub ty: Rc<StructType>,
+    pub fields: Vec<Value>,
+}
+
+// instances are equal when they have the same type and equal fields
+impl PartialEq for StructV {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.ty, &other.ty) && self.fields == other.fields
+    }
+}
+
+// StructOp - one of the procedures define-struct generates for a type
+#[derive(Debug, Clone)]
+pub struct StructOp {
+    pub ty: Rc<StructType>,
+    pub kind: StructOpKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructOpKind {
+    // make-name, takes a Value for each field
+    Constructor,
+    // name?, whether a Value is an instance
+    Predicate,
+    // name-field, the field at this index
+    Accessor(usize),
+}
+
+impl PartialEq for StructOp {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.ty, &other.ty) && self.kind == other.kind
+    }
+}
+
+impl StructOp {
+    // procedures - every procedure for a type, with the name define-struct binds it to
+    pub fn procedures(ty: &Rc<StructType>) -> Vec<StructOp> {
+        let mut kinds = vec![StructOpKind::Constructor, StructOpKind::Predicate];
+        kinds.extend((0..ty.fields.len()).map(StructOpKind::Accessor));
+        kinds
+            .into_iter()
+            .map(|kind| StructOp {
+                ty: ty.clone(),
+                kind,
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self.kind {
+            StructOpKind::Constructor => format!("make-{}", self.ty.name),
+            StructOpKind::Predicate => format!("{}?", self.ty.name),
+            StructOpKind::Accessor(i) => format!("{}-{}", self.ty.name, self.ty.fields[i]),
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self.kind {
+            StructOpKind::Constructor => self.ty.fields.len(),
+            StructOpKind::Predicate | StructOpKind::Accessor(_) => 1,
+        }
+    }
 }
 
 // CloV - Closures contain list of symbol params, body of ExprC, Env
@@ -21,10 +267,471 @@ pub struct CloV {
     pub env: Env,
 }
 
-// PrimV - Represents a primitive operator by its symbol
+// PrimV - Represents a primitive operator
 #[derive(Debug, Clone, PartialEq)]
 pub struct PrimV {
-    pub op: String,
+    pub op: PrimOp,
+}
+
+// PrimOp - the primitive operators, each declares its name and the types of its arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Leq,
+    Gt,
+    Geq,
+    NumEq,
+    Equal,
+    Not,
+    Abs,
+    Min,
+    Max,
+    Quotient,
+    Remainder,
+    Modulo,
+    Floor,
+    Ceiling,
+    Round,
+    Sqrt,
+    Expt,
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Substring,
+    Strlen,
+    StringAppend,
+    StringRef,
+    StringEq,
+    StringLt,
+    StringUpcase,
+    StringDowncase,
+    StringContains,
+    NumberToString,
+    StringToNumber,
+    StringSplit,
+    StringJoin,
+    IsSymbol,
+    SymbolToString,
+    Cons,
+    First,
+    Rest,
+    IsEmpty,
+    IsCons,
+    List,
+    Length,
+    Map,
+    Filter,
+    Foldl,
+    Foldr,
+    Apply,
+    BuildList,
+    Hash,
+    HashRef,
+    HashSet,
+    HashRemove,
+    HashKeys,
+    HashHasKey,
+    Void,
+    Error,
+}
+
+// ArgType - what a primitive accepts in one argument position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgType {
+    // any number, Real or Exact
+    Number,
+    // an Exact integer, for indices and counts
+    Integer,
+    String,
+    Symbol,
+    // a cons cell
+    Pair,
+    // a proper list, Null or Pairs ending in Null
+    List,
+    // a CloV, PrimV, HostV or StructOp
+    Procedure,
+    // an immutable hash map
+    HashMap,
+    // a Value that can be a hash key: a number, string, symbol or boolean
+    Key,
+    Any,
+}
+
+impl PrimOp {
+    // every primitive, in the order they are bound in top_env
+    pub const ALL: [PrimOp; 62] = [
+        PrimOp::Add,
+        PrimOp::Sub,
+        PrimOp::Mul,
+        PrimOp::Div,
+        PrimOp::Lt,
+        PrimOp::Leq,
+        PrimOp::Gt,
+        PrimOp::Geq,
+        PrimOp::NumEq,
+        PrimOp::Equal,
+        PrimOp::Not,
+        PrimOp::Abs,
+        PrimOp::Min,
+        PrimOp::Max,
+        PrimOp::Quotient,
+        PrimOp::Remainder,
+        PrimOp::Modulo,
+        PrimOp::Floor,
+        PrimOp::Ceiling,
+        PrimOp::Round,
+        PrimOp::Sqrt,
+        PrimOp::Expt,
+        PrimOp::Exp,
+        PrimOp::Log,
+        PrimOp::Sin,
+        PrimOp::Cos,
+        PrimOp::Substring,
+        PrimOp::Strlen,
+        PrimOp::StringAppend,
+        PrimOp::StringRef,
+        PrimOp::StringEq,
+        PrimOp::StringLt,
+        PrimOp::StringUpcase,
+        PrimOp::StringDowncase,
+        PrimOp::StringContains,
+        PrimOp::NumberToString,
+        PrimOp::StringToNumber,
+        PrimOp::StringSplit,
+        PrimOp::StringJoin,
+        PrimOp::IsSymbol,
+        PrimOp::SymbolToString,
+        PrimOp::Cons,
+        PrimOp::First,
+        PrimOp::Rest,
+        PrimOp::IsEmpty,
+        PrimOp::IsCons,
+        PrimOp::List,
+        PrimOp::Length,
+        PrimOp::Map,
+        PrimOp::Filter,
+        PrimOp::Foldl,
+        PrimOp::Foldr,
+        PrimOp::Apply,
+        PrimOp::BuildList,
+        PrimOp::Hash,
+        PrimOp::HashRef,
+        PrimOp::HashSet,
+        PrimOp::HashRemove,
+        PrimOp::HashKeys,
+        PrimOp::HashHasKey,
+        PrimOp::Void,
+        PrimOp::Error,
+    ];
+
+    // name - the symbol the primitive is bound to in top_env
+    pub fn name(self) -> &'static str {
+        match self {
+            PrimOp::Add => "+",
+            PrimOp::Sub => "-",
+            PrimOp::Mul => "*",
+            PrimOp::Div => "/",
+            PrimOp::Lt => "<",
+            PrimOp::Leq => "<=",
+            PrimOp::Gt => ">",
+            PrimOp::Geq => ">=",
+            PrimOp::NumEq => "=",
+            PrimOp::Equal => "equal?",
+            PrimOp::Not => "not",
+            PrimOp::Abs => "abs",
+            PrimOp::Min => "min",
+            PrimOp::Max => "max",
+            PrimOp::Quotient => "quotient",
+            PrimOp::Remainder => "remainder",
+            PrimOp::Modulo => "modulo",
+            PrimOp::Floor => "floor",
+            PrimOp::Ceiling => "ceiling",
+            PrimOp::Round => "round",
+            PrimOp::Sqrt => "sqrt",
+            PrimOp::Expt => "expt",
+            PrimOp::Exp => "exp",
+            PrimOp::Log => "log",
+            PrimOp::Sin => "sin",
+            PrimOp::Cos => "cos",
+            PrimOp::Substring => "substring",
+            PrimOp::Strlen => "strlen",
+            PrimOp::StringAppend => "string-append",
+            PrimOp::StringRef => "string-ref",
+            PrimOp::StringEq => "string=?",
+            PrimOp::StringLt => "string<?",
+            PrimOp::StringUpcase => "string-upcase",
+            PrimOp::StringDowncase => "string-downcase",
+            PrimOp::StringContains => "string-contains?",
+            PrimOp::NumberToString => "number->string",
+            PrimOp::StringToNumber => "string->number",
+            PrimOp::StringSplit => "string-split",
+            PrimOp::StringJoin => "string-join",
+            PrimOp::IsSymbol => "symbol?",
+            PrimOp::SymbolToString => "symbol->string",
+            PrimOp::Cons => "cons",
+            PrimOp::First => "first",
+            PrimOp::Rest => "rest",
+            PrimOp::IsEmpty => "empty?",
+            PrimOp::IsCons => "cons?",
+            PrimOp::List => "list",
+            PrimOp::Length => "length",
+            PrimOp::Map => "map",
+            PrimOp::Filter => "filter",
+            PrimOp::Foldl => "foldl",
+            PrimOp::Foldr => "foldr",
+            PrimOp::Apply => "apply",
+            PrimOp::BuildList => "build-list",
+            PrimOp::Hash => "hash",
+            PrimOp::HashRef => "hash-ref",
+            PrimOp::HashSet => "hash-set",
+            PrimOp::HashRemove => "hash-remove",
+            PrimOp::HashKeys => "hash-keys",
+            PrimOp::HashHasKey => "hash-has-key?",
+            PrimOp::Void => "void",
+            PrimOp::Error => "error",
+        }
+    }
+
+    // arg_types - the type of each argument, the arity is its length
+    pub fn arg_types(self) -> &'static [ArgType] {
+        use ArgType::*;
+        match self {
+            PrimOp::Add
+            | PrimOp::Sub
+            | PrimOp::Mul
+            | PrimOp::Div
+            | PrimOp::Lt
+            | PrimOp::Leq
+            | PrimOp::Gt
+            | PrimOp::Geq
+            | PrimOp::NumEq
+            | PrimOp::Min
+            | PrimOp::Max
+            | PrimOp::Quotient
+            | PrimOp::Remainder
+            | PrimOp::Modulo
+            | PrimOp::Expt => &[Number, Number],
+            PrimOp::Abs
+            | PrimOp::Floor
+            | PrimOp::Ceiling
+            | PrimOp::Round
+            | PrimOp::Sqrt
+            | PrimOp::Exp
+            | PrimOp::Log
+            | PrimOp::Sin
+            | PrimOp::Cos => &[Number],
+            PrimOp::Equal => &[Any, Any],
+            PrimOp::Not => &[Any],
+            PrimOp::Substring => &[String, Integer, Integer],
+            PrimOp::StringRef => &[String, Integer],
+            PrimOp::StringAppend
+            | PrimOp::StringEq
+            | PrimOp::StringLt
+            | PrimOp::StringContains
+            | PrimOp::StringSplit => &[String, String],
+            PrimOp::StringJoin => &[List, String],
+            PrimOp::Cons => &[Any, Any],
+            PrimOp::First | PrimOp::Rest => &[Pair],
+            PrimOp::IsEmpty | PrimOp::IsCons | PrimOp::IsSymbol => &[Any],
+            PrimOp::SymbolToString => &[Symbol],
+            // list takes any number of values, see rest_type
+            PrimOp::List => &[],
+            PrimOp::Length => &[List],
+            // map, foldl and foldr also take further lists, see rest_type
+            PrimOp::Map | PrimOp::Filter | PrimOp::Apply => &[Procedure, List],
+            PrimOp::Foldl | PrimOp::Foldr => &[Procedure, Any, List],
+            PrimOp::BuildList => &[Integer, Procedure],
+            // hash takes alternating keys and values, hash-ref an optional default, see rest_type
+            PrimOp::Hash => &[],
+            PrimOp::HashRef | PrimOp::HashRemove | PrimOp::HashHasKey => &[HashMap, Key],
+            PrimOp::HashSet => &[HashMap, Key, Any],
+            PrimOp::HashKeys => &[HashMap],
+            PrimOp::Void => &[],
+            PrimOp::Strlen
+            | PrimOp::StringUpcase
+            | PrimOp::StringDowncase
+            | PrimOp::StringToNumber
+            | PrimOp::Error => &[String],
+            PrimOp::NumberToString => &[Number],
+        }
+    }
+
+    // rest_type - for a primitive taking any number of further arguments, their type
+    pub fn rest_type(self) -> Option<ArgType> {
+        match self {
+            PrimOp::List | PrimOp::Hash | PrimOp::HashRef => Some(ArgType::Any),
+            PrimOp::Map | PrimOp::Foldl | PrimOp::Foldr => Some(ArgType::List),
+            _ => None,
+        }
+    }
+}
+
+// HostV - a primitive defined by the embedding Rust program, see Registry
+#[derive(Clone)]
+pub struct HostV {
+    pub name: String,
+    pub arity: Arity,
+    pub func: Rc<dyn Fn(Vec<Value>) -> Value>,
+}
+
+// Arity - how many arguments a HostV accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(self, n: usize) -> bool {
+        match self {
+            Arity::Exact(k) => n == k,
+            Arity::AtLeast(k) => n >= k,
+        }
+    }
+}
+
+// host functions are compared by identity, Rust closures cannot be compared
+impl PartialEq for HostV {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl fmt::Debug for HostV {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostV({}, {:?})", self.name, self.arity)
+    }
+}
+
+// Registry - host functions to bind in the top env next to the built-in primitives
+#[derive(Clone, Default)]
+pub struct Registry {
+    fns: Vec<HostV>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    // register - binds name to a Rust closure, called with the (forced) argument Values
+    // once their count has been checked against arity
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        func: impl Fn(Vec<Value>) -> Value + 'static,
+    ) -> &mut Self {
+        self.fns.push(HostV {
+            name: name.into(),
+            arity,
+            func: Rc::new(func),
+        });
+        self
+    }
+
+    // register_fn - binds name to a typed Rust function, its arity and argument types are checked
+    // through FromValue, and its result converted through IntoValue
+    pub fn register_fn<Args>(
+        &mut self,
+        name: &str,
+        func: impl HostFn<Args> + 'static,
+    ) -> &mut Self {
+        let arity = Arity::Exact(func.arity());
+        let owned = name.to_string();
+        self.register(name, arity, move |args| func.call(&owned, args))
+    }
+
+    // env - base_env(), prelude included, extended with a HostV binding for every registered function.
+    // A registered function shadows a prelude procedure of the same name
+    pub fn env(&self) -> Env {
+        let mut env = base_env(Config::default());
+        for host in &self.fns {
+            env.push(Binding {
+                name: host.name.clone(),
+                val: Box::new(Value::HostV(host.clone())),
+            });
+        }
+        env
+    }
+}
+
+// Thunk - a delayed argument under lazy evaluation, shared so it is only forced once.
+// Top-level definitions are also Thunks, so every definition can be in scope before it has a Value,
+// see interp_program for how a defined closure refers back to its own definition
+#[derive(Clone)]
+pub struct Thunk {
+    pub state: Rc<RefCell<ThunkState>>,
+}
+
+// ThunkState - the unevaluated expression with its Env, the memoized Value,
+// a named definition that has not been evaluated yet, or a named definition as its own program sees it,
+// held weakly so a closure it defines does not keep itself alive
+#[derive(Debug, Clone)]
+pub enum ThunkState {
+    Delayed(Box<ExprC>, Env, Config),
+    Forced(Value),
+    Undefined(String),
+    Defined(String, Weak<RefCell<ThunkState>>),
+}
+
+// thunks are compared by identity, two thunks are equal only if they share the same state
+impl PartialEq for Thunk {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Debug for Thunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.state.borrow() {
+            ThunkState::Delayed(expr, _, _) => write!(f, "Thunk(Delayed({:?}))", expr),
+            // a definition's Value may be a closure whose Env holds this very Thunk, so it is not printed
+            ThunkState::Forced(_) => write!(f, "Thunk(Forced)"),
+            ThunkState::Undefined(name) => write!(f, "Thunk(Undefined({}))", name),
+            ThunkState::Defined(name, _) => write!(f, "Thunk(Defined({}))", name),
+        }
+    }
+}
+
+// Strategy - how AppC passes arguments: Strict (call-by-value) or Lazy (call-by-need)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Strategy {
+    #[default]
+    Strict,
+    Lazy,
+}
+
+// Scope - which Env a closure body is evaluated in: Static (the closure's) or Dynamic (the caller's)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Scope {
+    #[default]
+    Static,
+    Dynamic,
+}
+
+// Config - evaluation options threaded through interp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Config {
+    pub strategy: Strategy,
+    pub scope: Scope,
+    // whether programs start from base_env's prelude definitions or from top_env alone
+    pub prelude: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            strategy: Strategy::default(),
+            scope: Scope::default(),
+            prelude: true,
+        }
+    }
 }
 
 // Binding : pair of a Symbol and a Value
@@ -37,15 +744,23 @@ pub struct Binding {
 // Env : a list of Bindings
 pub type Env = Vec<Binding>;
 
-// ExprC type : NumC, IfC, IdC, AppC, LamC, StringC
+// ExprC type : NumC, ExactC, IfC, IdC, AppC, LamC, StringC, MatchC, ValC, ExpansionC
 #[derive(Debug, Clone, PartialEq)]
 pub enum ExprC {
     NumC(NumC),
+    ExactC(ExactC),
     StringC(StringC),
+    BoolC(BoolC),
     IdC(IdC),
     IfC(IfC),
     AppC(AppC),
     LamC(LamC),
+    MatchC(MatchC),
+    QuoteC(QuoteC),
+    QuasiC(QuasiC),
+    VoidC,
+    ValC(ValC),
+    ExpansionC(ExpansionC),
 }
 
 // NumC : a Real
@@ -54,24 +769,78 @@ pub struct NumC {
     pub n: f64,
 }
 
+// ExactC : an exact integer or rational
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExactC {
+    pub n: Rational,
+}
+
 // StringC : a String
 #[derive(Debug, Clone, PartialEq)]
 pub struct StringC {
     pub s: String,
 }
 
+// BoolC : a boolean literal, #t or #f, which unlike the ids true and false cannot be shadowed
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoolC {
+    pub b: bool,
+}
+
+// ValC : an already evaluated Value, which the substitution evaluator puts in place of a bound id when the
+// Value has no literal syntax, like a closure, struct or hash. The parser never produces one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValC {
+    pub v: Value,
+}
+
+// ExpansionC : the ExprC a macro use expanded to, site is the use as written. Errors raised
+// while evaluating e name the use site
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpansionC {
+    pub site: String,
+    pub e: Box<ExprC>,
+}
+
 // IdC : a symbol representing an ID
 #[derive(Debug, Clone, PartialEq)]
 pub struct IdC {
     pub name: String,
 }
 
-// IfC : an if statement of ExprC, and ExprC's to act on if true or false
+// IfC : an if statement of ExprC, and ExprC's to act on if true or false.
+// form is the form it was written as, cond, and, or, when and unless desugar into IfC
 #[derive(Debug, Clone, PartialEq)]
 pub struct IfC {
     pub v: Box<ExprC>,
     pub iftrue: Box<ExprC>,
     pub iffalse: Box<ExprC>,
+    pub form: IfForm,
+}
+
+// IfForm - the form an IfC came from, its errors name this form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IfForm {
+    #[default]
+    If,
+    Cond,
+    And,
+    Or,
+    When,
+    Unless,
+}
+
+impl IfForm {
+    pub fn name(self) -> &'static str {
+        match self {
+            IfForm::If => "if",
+            IfForm::Cond => "cond",
+            IfForm::And => "and",
+            IfForm::Or => "or",
+            IfForm::When => "when",
+            IfForm::Unless => "unless",
+        }
+    }
 }
 
 // AppC : Represents a function application.function ExprC with a list of arg ExprC's
@@ -88,12 +857,137 @@ pub struct LamC {
     pub body: Box<ExprC>,
 }
 
-// reserved-keywords - a list of key-words
-const RESERVED_KEYWORDS: [&str; 7] = ["if", "lambda", "let", "=", "in", "end", "else"];
+// MatchC - the value of the first clause whose pattern matches v and whose guard holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchC {
+    pub v: Box<ExprC>,
+    pub clauses: Vec<MatchClause>,
+}
+
+// MatchClause - a pattern, an optional #:when guard, and the body, the guard and body see the pattern's ids
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchClause {
+    pub pat: Pattern,
+    pub guard: Option<Box<ExprC>>,
+    pub body: Box<ExprC>,
+}
+
+// QuoteC - a quoted datum, {quote d} or 'd, whose value is the datum as data
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteC {
+    pub datum: Sexp,
+}
+
+// QuasiC - a quasiquoted datum, {quasiquote t} or `t, the unquoted parts of its template are evaluated
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuasiC {
+    pub template: Template,
+}
+
+// Template - a quasiquote template: a datum with no unquotes, an unquoted expression,
+// or a list with unquotes somewhere inside
+#[derive(Debug, Clone, PartialEq)]
+pub enum Template {
+    Datum(Sexp),
+    Unquote(Box<ExprC>),
+    List(Vec<Template>),
+}
+
+impl Template {
+    // unquoted - the unquoted expressions, left to right, in the order build_template evaluates them
+    pub fn unquoted(&self) -> Vec<&ExprC> {
+        match self {
+            Template::Datum(_) => Vec::new(),
+            Template::Unquote(e) => vec![e],
+            Template::List(items) => items.iter().flat_map(Template::unquoted).collect(),
+        }
+    }
+}
+
+// Pattern - _ matches anything, an Id matches anything and binds it, literals match by equal?,
+// Cons matches a Pair (list patterns are Cons ending in Null), Struct matches an instance by its fields
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    Id(String),
+    Num(f64),
+    Exact(Rational),
+    Str(String),
+    Bool(bool),
+    Null,
+    // a quoted datum, e.g. 'lambda, matched by equal?
+    Quote(Sexp),
+    Cons(Box<Pattern>, Box<Pattern>),
+    Struct(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    // vars - the ids the pattern binds, left to right, in the order match_pattern gives their Values
+    pub fn vars(&self) -> Vec<String> {
+        let mut vars = Vec::new();
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, acc: &mut Vec<String>) {
+        match self {
+            Pattern::Id(name) => acc.push(name.clone()),
+            Pattern::Cons(first, rest) => {
+                first.collect_vars(acc);
+                rest.collect_vars(acc);
+            }
+            Pattern::Struct(_, fields) => {
+                for f in fields {
+                    f.collect_vars(acc);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// TopLevel - one form of a SHEQ program, a definition, a struct definition (its name and fields)
+// or an expression whose value is printed
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopLevel {
+    Define(String, ExprC),
+    DefineStruct(String, Vec<String>),
+    // paths of the modules whose exports the program imports, see module::Modules
+    Require(Vec<String>),
+    // names the program exports when another program requires it
+    Provide(Vec<String>),
+    Expr(ExprC),
+}
 
-// top_env
+// reserved-keywords - a list of key-words
+// = is numeric equality, let finds the = in its clauses by position so it need not be reserved
+const RESERVED_KEYWORDS: [&str; 21] = [
+    "if",
+    "lambda",
+    "let",
+    "in",
+    "end",
+    "else",
+    "define",
+    "define-struct",
+    "define-syntax",
+    "syntax-rules",
+    "match",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "cond",
+    "and",
+    "or",
+    "when",
+    "unless",
+    "require",
+    "provide",
+];
+
+// top_env - true, false and a PrimV for every PrimOp
 pub fn top_env() -> Env {
-    vec![
+    let mut env = vec![
         Binding {
             name: "true".into(),
             val: Box::new(Value::Boolean(true)),
@@ -103,74 +997,84 @@ pub fn top_env() -> Env {
             val: Box::new(Value::Boolean(false)),
         },
         Binding {
-            name: "+".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "+".into() })),
-        },
-        Binding {
-            name: "-".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "-".into() })),
-        },
-        Binding {
-            name: "*".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "*".into() })),
-        },
-        Binding {
-            name: "/".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "/".into() })),
-        },
-        Binding {
-            name: "<=".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "<=".into() })),
-        },
-        Binding {
-            name: "equal?".into(),
-            val: Box::new(Value::PrimV(PrimV {
-                op: "equal?".into(),
-            })),
-        },
-        Binding {
-            name: "substring".into(),
-            val: Box::new(Value::PrimV(PrimV {
-                op: "substring".into(),
-            })),
-        },
-        Binding {
-            name: "strlen".into(),
-            val: Box::new(Value::PrimV(PrimV {
-                op: "strlen".into(),
-            })),
-        },
-        Binding {
-            name: "error".into(),
-            val: Box::new(Value::PrimV(PrimV { op: "error".into() })),
+            name: "empty".into(),
+            val: Box::new(Value::Null),
         },
-    ]
+    ];
+    for op in PrimOp::ALL {
+        env.push(Binding {
+            name: op.name().into(),
+            val: Box::new(Value::PrimV(PrimV { op })),
+        });
+    }
+    env
+}
+
+// PRELUDE - library procedures written in SHEQ, compose, curry, range, append and the like
+const PRELUDE: &str = include_str!("prelude.sheq");
+
+thread_local! {
+    // PRELUDE_ENV - top_env extended with the PRELUDE, evaluated once per thread on first use
+    static PRELUDE_ENV: Env = {
+        let mut env = top_env();
+        interp_program(&parse_program(PRELUDE), &mut env, Config::default());
+        env
+    };
+}
+
+// base_env - the Env a program starts in: top_env, extended with the PRELUDE if config.prelude.
+// Every call shares the same prelude definitions, the PRELUDE is only evaluated once
+pub fn base_env(config: Config) -> Env {
+    if config.prelude {
+        PRELUDE_ENV.with(|env| env.clone())
+    } else {
+        top_env()
+    }
 }
 
-// interp - takes the complete AST (ExprC) with an Env, returning a Value
+// interp - takes the complete AST (ExprC) with an Env, returning a Value (strict evaluation)
 fn interp(e: &ExprC, env: &Env) -> Value {
+    interp_with(e, env, Config::default())
+}
+
+// interp_with - interp under the given Config, under Lazy the result may be an unforced Thunk
+pub fn interp_with(e: &ExprC, env: &Env, config: Config) -> Value {
     match e {
         ExprC::NumC(NumC { n }) => Value::Real(*n),
+        ExprC::ExactC(ExactC { n }) => Value::Exact(n.clone()),
         ExprC::StringC(StringC { s }) => Value::String(s.clone()),
+        ExprC::BoolC(BoolC { b }) => Value::Boolean(*b),
         ExprC::IdC(IdC { name }) => {
             if is_reserved(name) {
                 panic!("SHEQ: id name is a reserved word, got {}", name);
-            } else {
-                get_binding_val(name, env)
+            }
+            match config.strategy {
+                // only a definition can be a Thunk here, and it must already have its Value
+                Strategy::Strict => force(get_binding_val(name, env)),
+                Strategy::Lazy => get_binding_val(name, env),
             }
         }
-        ExprC::IfC(IfC { v, iftrue, iffalse }) => {
-            let test_val = interp(v, env);
+        ExprC::IfC(IfC {
+            v,
+            iftrue,
+            iffalse,
+            form,
+        }) => {
+            let test_val = force(interp_with(v, env, config));
             match test_val {
                 Value::Boolean(b) => {
                     if b {
-                        interp(iftrue, env)
+                        interp_with(iftrue, env, config)
                     } else {
-                        interp(iffalse, env)
+                        interp_with(iffalse, env, config)
                     }
                 }
                 other => {
-                    panic!("SHEQ: if expected boolean test, got {:?}", other);
+                    panic!(
+                        "SHEQ: {} expected boolean test, got {}",
+                        form.name(),
+                        serialize(&other)
+                    );
                 }
             }
         }
@@ -179,196 +1083,940 @@ fn interp(e: &ExprC, env: &Env) -> Value {
             body: body.clone(),
             env: env.clone(),
         }),
+        ExprC::QuoteC(QuoteC { datum }) => datum_value(datum),
+        ExprC::VoidC => Value::Void,
+        ExprC::ValC(ValC { v }) => v.clone(),
+        ExprC::ExpansionC(ExpansionC { site, e }) => {
+            at_use_site(site, || interp_with(e, env, config))
+        }
+        ExprC::QuasiC(QuasiC { template }) => {
+            build_template(template, &mut |e| force(interp_with(e, env, config)))
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => interp_match(v, clauses, env, config),
         ExprC::AppC(AppC { expr, args }) => {
-            let f_val = interp(expr, env);
-            let arg_vals: Vec<Value> = args.iter().map(|a| interp(a, env)).collect();
-
-            match f_val {
-                Value::CloV(clo) => {
-                    if arg_vals.len() != clo.params.len() {
-                        panic!(
-                            "SHEQ: Incorrect number of arguments, got {}, expected {}",
-                            arg_vals.len(),
-                            clo.params.len()
-                        );
-                    }
-                    // extend the env
-                    let new_env = create_env(&clo.params, &arg_vals, &clo.env);
-                    interp(&clo.body, &new_env)
-                }
-                Value::PrimV(prim) => interp_prim(&prim, arg_vals),
-                other => panic!("SHEQ: attempted to apply non function value of {:?}", other),
-            }
+            let f_val = force(interp_with(expr, env, config));
+            let arg_vals: Vec<Value> = args
+                .iter()
+                .map(|a| match config.strategy {
+                    Strategy::Strict => interp_with(a, env, config),
+                    Strategy::Lazy => delay(a, env, config),
+                })
+                .collect();
+
+            apply_value(f_val, arg_vals, env, config)
         }
     }
 }
 
-// interp_prim - interprets primops, takesa PrimV and a list of Values, returns a Value
-fn interp_prim(prim: &PrimV, args: Vec<Value>) -> Value {
-    match prim.op.as_str() {
-        "+" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::Real(a), Value::Real(b)] => Value::Real(a + b),
-                // correct arity but wrong types
-                [_, _] => {
-                    panic!("SHEQ: Primv + expected 2 numbers, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
-            }
+// interp_match - the value of the body of the first clause that matches the value of v, kept out of
+// interp_with so the frame every nested expression pushes stays small
+fn interp_match(v: &ExprC, clauses: &[MatchClause], env: &Env, config: Config) -> Value {
+    let val = force(interp_with(v, env, config));
+    let struct_type = |name: &str| struct_type_named(name, env);
+    for clause in clauses {
+        let mut binds = Vec::new();
+        if !match_pattern(&clause.pat, &val, &struct_type, &mut binds) {
+            continue;
         }
-        "-" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::Real(a), Value::Real(b)] => Value::Real(a - b),
-                // correct arity but wrong types
-                [_, _] => {
-                    panic!("SHEQ: Primv - expected 2 numbers, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
+        // pattern ids are bound like a closure's params
+        let new_env = create_env(&clause.pat.vars(), &binds, env);
+        if let Some(guard) = &clause.guard {
+            match force(interp_with(guard, &new_env, config)) {
+                Value::Boolean(true) => {}
+                Value::Boolean(false) => continue,
+                other => panic!(
+                    "SHEQ: match guard expected boolean, got {}",
+                    serialize(&other)
+                ),
             }
         }
-        "*" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::Real(a), Value::Real(b)] => Value::Real(a * b),
-                // correct arity but wrong types
-                [_, _] => {
-                    panic!("SHEQ: Primv * expected 2 numbers, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
-            }
+        return interp_with(&clause.body, &new_env, config);
+    }
+    panic!("SHEQ: no match clause matched {}", serialize(&val))
+}
+
+// datum_value - a datum as data: lists become proper lists and symbols Symbols, even true, false and empty
+pub(crate) fn datum_value(s: &Sexp) -> Value {
+    match s {
+        Sexp::Num(n) => Value::Real(*n),
+        Sexp::Exact(n) => Value::Exact(n.clone()),
+        Sexp::Str(s) => Value::String(s.clone()),
+        Sexp::Sym(name) => Value::Symbol(name.clone()),
+        Sexp::List(items) => Value::list(items.iter().map(datum_value).collect()),
+    }
+}
+
+// build_template - the value of a quasiquote template, eval gives the value of each unquoted expression
+pub(crate) fn build_template(t: &Template, eval: &mut dyn FnMut(&ExprC) -> Value) -> Value {
+    match t {
+        Template::Datum(datum) => datum_value(datum),
+        Template::Unquote(e) => eval(e),
+        Template::List(items) => {
+            Value::list(items.iter().map(|i| build_template(i, eval)).collect())
         }
-        "/" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::Real(a), Value::Real(b)] => {
-                    if *b != 0.0 {
-                        Value::Real(a / b)
-                    } else {
-                        panic!("SHEQ: Divide by zero error")
-                    }
-                }
-                // correct arity but wrong types
-                [_, _] => {
-                    panic!("SHEQ: Primv / expected 2 numbers, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
-            }
+    }
+}
+
+// match_pattern - whether v matches pat, pushing the Value of each pattern id onto binds,
+// struct_type finds the type a struct pattern names
+pub(crate) fn match_pattern(
+    pat: &Pattern,
+    v: &Value,
+    struct_type: &dyn Fn(&str) -> Rc<StructType>,
+    binds: &mut Vec<Value>,
+) -> bool {
+    match (pat, v) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Id(_), _) => {
+            binds.push(v.clone());
+            true
         }
-        "<=" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::Real(a), Value::Real(b)] => {
-                    if a <= b {
-                        Value::Boolean(true)
-                    } else {
-                        Value::Boolean(false)
-                    }
-                }
-                // correct arity but wrong types
-                [_, _] => {
-                    panic!("SHEQ: Primv <= expected 2 numbers, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
-            }
+        (Pattern::Num(n), Value::Real(x)) => real_bits(*n) == real_bits(*x),
+        (Pattern::Exact(n), Value::Exact(x)) => n == x,
+        (Pattern::Str(s), Value::String(x)) => s == x,
+        (Pattern::Bool(b), Value::Boolean(x)) => b == x,
+        (Pattern::Null, Value::Null) => true,
+        (Pattern::Quote(datum), _) => datum_value(datum) == *v,
+        (Pattern::Cons(first, rest), Value::Pair(pair)) => {
+            match_pattern(first, &pair.first, struct_type, binds)
+                && match_pattern(rest, &pair.rest, struct_type, binds)
         }
-        "equal?" => {
-            match args.as_slice() {
-                // correct arity
-                [a, b] => {
-                    if a == b {
-                        Value::Boolean(true)
-                    } else {
-                        Value::Boolean(false)
-                    }
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
-                }
+        (Pattern::Struct(name, fields), _) => {
+            let ty = struct_type(name);
+            if fields.len() != ty.fields.len() {
+                panic!(
+                    "SHEQ: match pattern for {} expected {} fields, got {}",
+                    name,
+                    ty.fields.len(),
+                    fields.len()
+                );
+            }
+            match v {
+                Value::StructV(sv) if Rc::ptr_eq(&sv.ty, &ty) => fields
+                    .iter()
+                    .zip(&sv.fields)
+                    .all(|(p, f)| match_pattern(p, f, struct_type, binds)),
+                _ => false,
             }
         }
-        "substring" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::String(string), Value::Real(start), Value::Real(stop)] => {
-                    if start.fract() != 0.0 || stop.fract() != 0.0 {
-                        panic!(
-                            "SHEQ: substring expected integer indices, got {} and {}",
-                            start, stop
-                        );
-                    }
+        _ => false,
+    }
+}
 
-                    let start_i = *start as usize;
-                    let stop_i = *stop as usize;
+// struct_type_named - the struct type whose predicate name? is bound in env
+pub(crate) fn struct_type_named(name: &str, env: &Env) -> Rc<StructType> {
+    let predicate = format!("{}?", name);
+    match env
+        .iter()
+        .rev()
+        .find(|b| b.name == predicate)
+        .map(|b| force((*b.val).clone()))
+    {
+        Some(Value::StructOp(StructOp {
+            ty,
+            kind: StructOpKind::Predicate,
+        })) => ty,
+        _ => panic!("SHEQ: match pattern names no struct, got {}", name),
+    }
+}
 
-                    if start_i <= stop_i && stop_i <= string.len() {
-                        Value::String(string[start_i..stop_i].to_string())
-                    } else {
-                        panic!("SHEQ: string index out of range")
-                    }
-                }
-                // correct arity but wrong types
-                [_, _, _] => {
-                    panic!(
-                        "SHEQ: Primv substring expected 1 string and 2 numbers, got {:?}",
-                        args
-                    );
+// apply_value - applies a function Value to argument Values, env is the caller's for dynamic scope
+pub(crate) fn apply_value(f_val: Value, arg_vals: Vec<Value>, env: &Env, config: Config) -> Value {
+    match f_val {
+        Value::CloV(clo) => {
+            if arg_vals.len() != clo.params.len() {
+                panic!(
+                    "SHEQ: Incorrect number of arguments, got {}, expected {}",
+                    arg_vals.len(),
+                    clo.params.len()
+                );
+            }
+            // extend the closure's env, or the caller's under dynamic scope
+            let base_env = match config.scope {
+                Scope::Static => &clo.env,
+                Scope::Dynamic => env,
+            };
+            let new_env = create_env(&clo.params, &arg_vals, base_env);
+            interp_with(&clo.body, &new_env, config)
+        }
+        Value::PrimV(prim) => interp_prim(
+            &prim,
+            arg_vals.into_iter().map(force).collect(),
+            env,
+            config,
+        ),
+        Value::HostV(host) => apply_host(&host, arg_vals.into_iter().map(force).collect()),
+        Value::StructOp(op) => apply_struct_op(&op, arg_vals.into_iter().map(force).collect()),
+        other => panic!("SHEQ: attempted to apply non function value of {:?}", other),
+    }
+}
+
+// delay - builds the argument Value for a lazy application, literals, lambdas and ids need no Thunk
+fn delay(e: &ExprC, env: &Env, config: Config) -> Value {
+    match e {
+        ExprC::NumC(_)
+        | ExprC::ExactC(_)
+        | ExprC::StringC(_)
+        | ExprC::BoolC(_)
+        | ExprC::IdC(_)
+        | ExprC::LamC(_)
+        | ExprC::QuoteC(_)
+        | ExprC::VoidC
+        | ExprC::ValC(_) => interp_with(e, env, config),
+        _ => Value::Thunk(Thunk {
+            state: Rc::new(RefCell::new(ThunkState::Delayed(
+                Box::new(e.clone()),
+                env.clone(),
+                config,
+            ))),
+        }),
+    }
+}
+
+// force - evaluates a Thunk (memoizing the result), any other Value is returned as is
+pub fn force(v: Value) -> Value {
+    match v {
+        Value::Thunk(thunk) => {
+            let state = thunk.state.borrow().clone();
+            match state {
+                ThunkState::Forced(v) => v,
+                ThunkState::Delayed(expr, env, config) => {
+                    let v = force(interp_with(&expr, &env, config));
+                    *thunk.state.borrow_mut() = ThunkState::Forced(v.clone());
+                    v
                 }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
+                ThunkState::Undefined(name) => {
+                    panic!("SHEQ: {} used before its definition", name)
                 }
+                ThunkState::Defined(name, cell) => match cell.upgrade() {
+                    Some(state) => force(Value::Thunk(Thunk { state })),
+                    None => panic!(
+                        "SHEQ: {} is no longer defined, the Env of the program that defined it is gone",
+                        name
+                    ),
+                },
             }
         }
-        "strlen" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::String(s)] => Value::Real(s.len() as f64),
-                // correct arity but wrong types
-                [_] => {
-                    panic!("SHEQ: Primv strlen expected string, got {:?}", args);
-                }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
+        other => other,
+    }
+}
+
+// interp_program - evaluates the forms of a program in order, extending env with its definitions.
+// Every definition is bound before any form runs so definitions can be mutually recursive,
+// struct procedures need no evaluation so they are bound to their StructOp right away.
+// Require and provide are left to module::Modules::run_program, which binds the imports first.
+// Returns the (forced) Value of each expression form.
+// env owns the definitions' Thunks, the forms themselves run in a copy of env that refers to them
+// weakly, so a defined closure capturing that copy makes no Rc cycle with its own definition.
+// A closure that outlives env can no longer use the definitions of its program
+pub fn interp_program(forms: &[TopLevel], env: &mut Env, config: Config) -> Vec<Value> {
+    let mut defined: Vec<String> = Vec::new();
+    let mut define_name = |name: &str| {
+        if defined.iter().any(|n| n == name) {
+            panic!("SHEQ: duplicate definition of {}", name);
+        }
+        defined.push(name.to_string());
+    };
+    let mut cells = Vec::new();
+    for form in forms {
+        match form {
+            TopLevel::Define(name, _) => {
+                define_name(name);
+                let cell = Rc::new(RefCell::new(ThunkState::Undefined(name.clone())));
+                env.push(Binding {
+                    name: name.clone(),
+                    val: Box::new(Value::Thunk(Thunk {
+                        state: cell.clone(),
+                    })),
+                });
+                cells.push((env.len() - 1, cell));
+            }
+            TopLevel::DefineStruct(name, fields) => {
+                let ty = Rc::new(StructType {
+                    name: name.clone(),
+                    fields: fields.clone(),
+                });
+                for op in StructOp::procedures(&ty) {
+                    define_name(&op.name());
+                    env.push(Binding {
+                        name: op.name(),
+                        val: Box::new(Value::StructOp(op)),
+                    });
                 }
             }
+            TopLevel::Require(_) | TopLevel::Provide(_) | TopLevel::Expr(_) => {}
+        }
+    }
+
+    // scope - env with each definition of this program bound to a weak reference to its Thunk
+    let mut scope = env.clone();
+    for (i, cell) in &cells {
+        let defined = ThunkState::Defined(scope[*i].name.clone(), Rc::downgrade(cell));
+        *scope[*i].val = Value::Thunk(Thunk {
+            state: Rc::new(RefCell::new(defined)),
+        });
+    }
+
+    let mut cells = cells.into_iter().map(|(_, cell)| cell);
+    let mut results = Vec::new();
+    for form in forms {
+        match form {
+            TopLevel::DefineStruct(..) | TopLevel::Require(_) | TopLevel::Provide(_) => {}
+            TopLevel::Define(_, expr) => {
+                let cell = cells.next().expect("SHEQ: missing definition cell");
+                let state = match config.strategy {
+                    Strategy::Strict => {
+                        ThunkState::Forced(force(interp_with(expr, &scope, config)))
+                    }
+                    Strategy::Lazy => {
+                        ThunkState::Delayed(Box::new(expr.clone()), scope.clone(), config)
+                    }
+                };
+                *cell.borrow_mut() = state;
+            }
+            TopLevel::Expr(e) => results.push(force(interp_with(e, &scope, config))),
+        }
+    }
+    results
+}
+
+// interp_prim - interprets primops, takes a PrimV and a list of Values, returns a Value.
+// env and config are the application's, the higher-order primitives pass them on when they call
+// back into their procedure argument
+fn interp_prim(prim: &PrimV, args: Vec<Value>, env: &Env, config: Config) -> Value {
+    check_prim_args(prim.op, &args);
+    // call - applies a procedure argument the same way AppC does
+    let call = |f: &Value, f_args: Vec<Value>| {
+        check_callback(prim.op, f, f_args.len());
+        force(apply_value(f.clone(), f_args, env, config))
+    };
+    match (prim.op, args.as_slice()) {
+        (PrimOp::Add, [a, b]) => arith(a, b, |x, y| x + y, |x, y| x + y),
+        (PrimOp::Sub, [a, b]) => arith(a, b, |x, y| x - y, |x, y| x - y),
+        (PrimOp::Mul, [a, b]) => arith(a, b, |x, y| x * y, |x, y| x * y),
+        (PrimOp::Div, [a, b]) => {
+            if is_zero(b) {
+                panic!("SHEQ: Divide by zero error")
+            }
+            arith(a, b, |x, y| x / y, |x, y| x / y)
+        }
+        (PrimOp::Lt, [a, b]) => Value::Boolean(num_cmp(a, b) == Some(Ordering::Less)),
+        (PrimOp::Leq, [a, b]) => Value::Boolean(matches!(
+            num_cmp(a, b),
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        (PrimOp::Gt, [a, b]) => Value::Boolean(num_cmp(a, b) == Some(Ordering::Greater)),
+        (PrimOp::Geq, [a, b]) => Value::Boolean(matches!(
+            num_cmp(a, b),
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        (PrimOp::NumEq, [a, b]) => Value::Boolean(num_cmp(a, b) == Some(Ordering::Equal)),
+        (PrimOp::Equal, [a, b]) => Value::Boolean(a == b),
+        (PrimOp::Not, [a]) => Value::Boolean(*a == Value::Boolean(false)),
+        (PrimOp::Abs, [Value::Exact(n)]) => Value::Exact(n.abs()),
+        (PrimOp::Abs, [a]) => Value::Real(real_of(a).abs()),
+        (PrimOp::Min, [a, b]) => min_max(a, b, Ordering::Less),
+        (PrimOp::Max, [a, b]) => min_max(a, b, Ordering::Greater),
+        (PrimOp::Quotient | PrimOp::Remainder | PrimOp::Modulo, [a, b]) => {
+            integer_division(prim.op, a, b)
+        }
+        (PrimOp::Floor, [Value::Exact(n)]) => Value::Exact(n.floor()),
+        (PrimOp::Floor, [a]) => Value::Real(real_of(a).floor()),
+        (PrimOp::Ceiling, [Value::Exact(n)]) => Value::Exact(n.ceiling()),
+        (PrimOp::Ceiling, [a]) => Value::Real(real_of(a).ceil()),
+        (PrimOp::Round, [Value::Exact(n)]) => Value::Exact(n.round()),
+        (PrimOp::Round, [a]) => Value::Real(real_of(a).round_ties_even()),
+        (PrimOp::Sqrt, [a]) => {
+            if num_cmp(a, &Value::Exact(Rational::from(0))) == Some(Ordering::Less) {
+                panic!(
+                    "SHEQ: sqrt expected a non-negative number, got {}",
+                    serialize(a)
+                );
+            }
+            match a {
+                Value::Exact(n) => n
+                    .sqrt()
+                    .map(Value::Exact)
+                    .unwrap_or_else(|| Value::Real(n.to_f64().sqrt())),
+                _ => Value::Real(real_of(a).sqrt()),
+            }
+        }
+        (PrimOp::Expt, [a, b]) => expt(a, b),
+        (PrimOp::Exp, [Value::Exact(n)]) if n.is_zero() => Value::Exact(Rational::from(1)),
+        (PrimOp::Exp, [a]) => Value::Real(real_of(a).exp()),
+        (PrimOp::Log, [a]) => match a {
+            Value::Exact(n) if *n == Rational::from(1) => Value::Exact(Rational::from(0)),
+            Value::Exact(n) if n.is_zero() => panic!("SHEQ: log is undefined for exact 0"),
+            _ if real_of(a) < 0.0 => {
+                panic!(
+                    "SHEQ: log expected a non-negative number, got {}",
+                    serialize(a)
+                )
+            }
+            _ => Value::Real(real_of(a).ln()),
+        },
+        (PrimOp::Sin, [Value::Exact(n)]) if n.is_zero() => Value::Exact(Rational::from(0)),
+        (PrimOp::Sin, [a]) => Value::Real(real_of(a).sin()),
+        (PrimOp::Cos, [Value::Exact(n)]) if n.is_zero() => Value::Exact(Rational::from(1)),
+        (PrimOp::Cos, [a]) => Value::Real(real_of(a).cos()),
+        (
+            PrimOp::Substring,
+            [
+                Value::String(string),
+                Value::Exact(start),
+                Value::Exact(stop),
+            ],
+        ) => {
+            let start_i = index_of(start);
+            let stop_i = index_of(stop);
+
+            match char_slice(string, start_i, stop_i) {
+                Some(sub) => Value::String(sub.to_string()),
+                None => panic!("SHEQ: string index out of range"),
+            }
+        }
+        (PrimOp::Strlen, [Value::String(s)]) => {
+            Value::Exact(Rational::from(s.chars().count() as i64))
+        }
+        (PrimOp::StringAppend, [Value::String(a), Value::String(b)]) => {
+            Value::String(format!("{}{}", a, b))
+        }
+        (PrimOp::StringRef, [Value::String(string), Value::Exact(i)]) => {
+            let i = index_of(i);
+            match char_slice(string, i, i + 1) {
+                Some(c) => Value::String(c.to_string()),
+                None => panic!("SHEQ: string index out of range"),
+            }
+        }
+        (PrimOp::StringEq, [Value::String(a), Value::String(b)]) => Value::Boolean(a == b),
+        // byte order of UTF-8 is the order of the scalar values
+        (PrimOp::StringLt, [Value::String(a), Value::String(b)]) => Value::Boolean(a < b),
+        (PrimOp::StringUpcase, [Value::String(s)]) => Value::String(s.to_uppercase()),
+        (PrimOp::StringDowncase, [Value::String(s)]) => Value::String(s.to_lowercase()),
+        (PrimOp::StringContains, [Value::String(s), Value::String(sub)]) => {
+            Value::Boolean(s.contains(sub.as_str()))
+        }
+        (PrimOp::NumberToString, [n]) => Value::String(serialize(n)),
+        // like Racket, a string that is not a number gives false
+        (PrimOp::StringToNumber, [Value::String(s)]) => match read_number(s) {
+            Some(Sexp::Exact(n)) => Value::Exact(n),
+            Some(Sexp::Num(n)) => Value::Real(n),
+            _ => Value::Boolean(false),
+        },
+        (PrimOp::StringSplit, [Value::String(s), Value::String(sep)]) => {
+            if sep.is_empty() {
+                panic!("SHEQ: string-split expected a non-empty separator");
+            }
+            // like Racket, a separator at either end does not produce an empty string
+            let trimmed = s.strip_prefix(sep.as_str()).unwrap_or(s);
+            let trimmed = trimmed.strip_suffix(sep.as_str()).unwrap_or(trimmed);
+            if trimmed.is_empty() {
+                Value::Null
+            } else {
+                Value::list(
+                    trimmed
+                        .split(sep.as_str())
+                        .map(|part| Value::String(part.to_string()))
+                        .collect(),
+                )
+            }
+        }
+        (PrimOp::StringJoin, [strs, Value::String(sep)]) => {
+            let parts: Vec<String> = list_items(strs)
+                .iter()
+                .map(|v| match v {
+                    Value::String(s) => s.clone(),
+                    other => panic!(
+                        "SHEQ: string-join expected a list of strings, got {}",
+                        serialize(other)
+                    ),
+                })
+                .collect();
+            Value::String(parts.join(sep))
         }
-        "error" => {
-            match args.as_slice() {
-                // correct arity and types
-                [Value::String(e)] => panic!("SHEQ: {}", e),
-                // correct arity but wrong types
-                [_] => {
-                    panic!("SHEQ: Primv error expected string, got {:?}", args);
+        (PrimOp::IsSymbol, [a]) => Value::Boolean(matches!(a, Value::Symbol(_))),
+        (PrimOp::SymbolToString, [Value::Symbol(name)]) => Value::String(name.clone()),
+        (PrimOp::Cons, [a, b]) => Value::cons(a.clone(), b.clone()),
+        (PrimOp::First, [Value::Pair(pair)]) => pair.first.clone(),
+        (PrimOp::Rest, [Value::Pair(pair)]) => pair.rest.clone(),
+        (PrimOp::IsEmpty, [a]) => Value::Boolean(matches!(a, Value::Null)),
+        (PrimOp::IsCons, [a]) => Value::Boolean(matches!(a, Value::Pair(_))),
+        (PrimOp::List, items) => Value::list(items.to_vec()),
+        (PrimOp::Length, [l]) => Value::Exact(Rational::from(list_items(l).len() as i64)),
+        (PrimOp::Map, [f, lists @ ..]) => Value::list(
+            rows(prim.op, lists)
+                .into_iter()
+                .map(|row| call(f, row))
+                .collect(),
+        ),
+        (PrimOp::Filter, [f, l]) => Value::list(
+            list_items(l)
+                .into_iter()
+                .filter(|v| call(f, vec![v.clone()]) != Value::Boolean(false))
+                .collect(),
+        ),
+        // foldl passes each element then the accumulator, from the left
+        (PrimOp::Foldl, [f, init, lists @ ..]) => {
+            rows(prim.op, lists)
+                .into_iter()
+                .fold(init.clone(), |acc, mut row| {
+                    row.push(acc);
+                    call(f, row)
+                })
+        }
+        (PrimOp::Foldr, [f, init, lists @ ..]) => {
+            rows(prim.op, lists)
+                .into_iter()
+                .rev()
+                .fold(init.clone(), |acc, mut row| {
+                    row.push(acc);
+                    call(f, row)
+                })
+        }
+        (PrimOp::Apply, [f, l]) => call(f, list_items(l)),
+        (PrimOp::BuildList, [Value::Exact(n), f]) => {
+            let n = n
+                .to_integer()
+                .and_then(|n| n.to_i64())
+                .filter(|n| *n >= 0)
+                .unwrap_or_else(|| {
+                    panic!("SHEQ: build-list expected a non-negative count, got {}", n)
+                });
+            Value::list(
+                (0..n)
+                    .map(|i| call(f, vec![Value::Exact(Rational::from(i))]))
+                    .collect(),
+            )
+        }
+        (PrimOp::Hash, items) => {
+            if items.len() % 2 != 0 {
+                panic!(
+                    "SHEQ: hash expected an even number of arguments, got {}",
+                    items.len()
+                );
+            }
+            let mut map = HashMap::new();
+            for entry in items.chunks(2) {
+                if !is_key(&entry[0]) {
+                    panic!(
+                        "SHEQ: hash expected a hash key, got {}",
+                        serialize(&entry[0])
+                    );
                 }
-                // wrong arity
-                _ => {
-                    panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
+                map.insert(entry[0].clone(), entry[1].clone());
+            }
+            Value::Hash(Rc::new(map))
+        }
+        // the default is returned when the key is missing, or called with no arguments if it is a procedure
+        (PrimOp::HashRef, [Value::Hash(map), k, default @ ..]) => match (map.get(k), default) {
+            (Some(v), [] | [_]) => v.clone(),
+            (None, []) => panic!("SHEQ: hash-ref found no value for key {}", serialize(k)),
+            (None, [d]) => match d {
+                Value::CloV(_) | Value::PrimV(_) | Value::HostV(_) | Value::StructOp(_) => {
+                    call(d, Vec::new())
                 }
+                _ => d.clone(),
+            },
+            _ => panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len()),
+        },
+        // hash-set and hash-remove copy the map, the argument is left unchanged
+        (PrimOp::HashSet, [Value::Hash(map), k, v]) => {
+            let mut map = map.clone();
+            Rc::make_mut(&mut map).insert(k.clone(), v.clone());
+            Value::Hash(map)
+        }
+        (PrimOp::HashRemove, [Value::Hash(map), k]) => {
+            let mut map = map.clone();
+            Rc::make_mut(&mut map).remove(k);
+            Value::Hash(map)
+        }
+        (PrimOp::HashKeys, [Value::Hash(map)]) => Value::list(
+            hash_entries(map)
+                .into_iter()
+                .map(|(k, _)| k.clone())
+                .collect(),
+        ),
+        (PrimOp::HashHasKey, [Value::Hash(map), k]) => Value::Boolean(map.contains_key(k)),
+        (PrimOp::Void, []) => Value::Void,
+        (PrimOp::Error, [Value::String(e)]) => panic!("SHEQ: {}", e),
+        (op, _) => panic!(
+            "SHEQ: Primv {} was given unchecked arguments {:?}",
+            op.name(),
+            args
+        ),
+    }
+}
+
+// arith - applies the exact op when both numbers are exact, otherwise both become Reals
+fn arith(
+    a: &Value,
+    b: &Value,
+    exact: fn(&Rational, &Rational) -> Rational,
+    inexact: fn(f64, f64) -> f64,
+) -> Value {
+    match (a, b) {
+        (Value::Exact(x), Value::Exact(y)) => Value::Exact(exact(x, y)),
+        _ => Value::Real(inexact(real_of(a), real_of(b))),
+    }
+}
+
+// num_cmp - compares two numbers by value, exactly unless a float is infinite or NaN, None when unordered
+fn num_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    let exact = |v: &Value| match v {
+        Value::Exact(n) => Some(n.clone()),
+        _ => Rational::from_f64(real_of(v)),
+    };
+    match (exact(a), exact(b)) {
+        (Some(x), Some(y)) => Some(x.cmp(&y)),
+        _ => real_of(a).partial_cmp(&real_of(b)),
+    }
+}
+
+// min_max - picks a if it compares as want against b, otherwise b, inexact if either argument is
+fn min_max(a: &Value, b: &Value, want: Ordering) -> Value {
+    let picked = match num_cmp(a, b) {
+        None => return Value::Real(f64::NAN),
+        Some(o) if o == want => a,
+        Some(_) => b,
+    };
+    match (a, b) {
+        (Value::Exact(_), Value::Exact(_)) => picked.clone(),
+        _ => Value::Real(real_of(picked)),
+    }
+}
+
+// integer_division - quotient, remainder and modulo, on exact integers or on floats with integer values
+fn integer_division(op: PrimOp, a: &Value, b: &Value) -> Value {
+    let not_integers = || -> ! {
+        panic!(
+            "SHEQ: {} expected integers, got {} and {}",
+            op.name(),
+            serialize(a),
+            serialize(b)
+        )
+    };
+    match (a, b) {
+        (Value::Exact(x), Value::Exact(y)) => {
+            let (Some(x), Some(y)) = (x.to_integer(), y.to_integer()) else {
+                not_integers()
+            };
+            let (q, r) = x.divrem(y);
+            let result = match op {
+                PrimOp::Quotient => q,
+                // modulo takes the sign of the divisor
+                PrimOp::Modulo if !r.is_zero() && r.is_negative() != y.is_negative() => &r + y,
+                _ => r,
+            };
+            Value::Exact(Rational::from_integer(result))
+        }
+        _ => {
+            let (x, y) = (real_of(a), real_of(b));
+            if !x.is_finite() || !y.is_finite() || x.fract() != 0.0 || y.fract() != 0.0 {
+                not_integers()
+            }
+            if y == 0.0 {
+                panic!("SHEQ: Divide by zero error")
+            }
+            let r = x % y;
+            Value::Real(match op {
+                PrimOp::Quotient => (x / y).trunc(),
+                PrimOp::Modulo if r != 0.0 && (r < 0.0) != (y < 0.0) => r + y,
+                _ => r,
+            })
+        }
+    }
+}
+
+// MAX_EXPT_BITS - the size of the largest exact result expt builds, about 300,000 decimal digits
+const MAX_EXPT_BITS: u64 = 1_000_000;
+
+// expt - exact when an exact base has an exact integer power, otherwise a float.
+// An exact result bigger than MAX_EXPT_BITS is an error rather than an unbounded computation
+fn expt(a: &Value, b: &Value) -> Value {
+    if let (Value::Exact(base), Value::Exact(power)) = (a, b)
+        && let Some(power) = power.to_integer()
+    {
+        let bits = base.numer().bit_len().max(base.denom().bit_len()) as u64;
+        let power = match power.to_i64() {
+            // 0, 1 and -1 stay that small, only the sign and parity of the power matter
+            _ if bits <= 1 && !power.is_zero() => {
+                let parity = if power.is_even() { 2 } else { 1 };
+                if power.is_negative() { -parity } else { parity }
+            }
+            Some(p) if p.unsigned_abs().saturating_mul(bits) <= MAX_EXPT_BITS => p,
+            _ => panic!(
+                "SHEQ: expt result is too large to be exact, got {} and {}",
+                serialize(a),
+                serialize(b)
+            ),
+        };
+        return Value::Exact(base.pow(power));
+    }
+    let (x, y) = (real_of(a), real_of(b));
+    if x < 0.0 && y.fract() != 0.0 {
+        panic!(
+            "SHEQ: expt of a negative number needs an integer power, got {} and {}",
+            serialize(a),
+            serialize(b)
+        );
+    }
+    Value::Real(x.powf(y))
+}
+
+// real_of - a number as a float
+fn real_of(v: &Value) -> f64 {
+    match v {
+        Value::Real(n) => *n,
+        Value::Exact(n) => n.to_f64(),
+        other => panic!("SHEQ: expected a number, got {:?}", other),
+    }
+}
+
+fn is_zero(v: &Value) -> bool {
+    match v {
+        Value::Real(n) => *n == 0.0,
+        Value::Exact(n) => n.is_zero(),
+        _ => false,
+    }
+}
+
+// index_of - an exact integer argument as an index, negative indices are out of range
+fn index_of(n: &Rational) -> usize {
+    n.to_integer()
+        .and_then(|i| i.to_i64())
+        .and_then(|i| usize::try_from(i).ok())
+        .unwrap_or_else(|| panic!("SHEQ: string index out of range"))
+}
+
+// rows - the i-th elements of each list, for primitives that walk several lists together
+fn rows(op: PrimOp, lists: &[Value]) -> Vec<Vec<Value>> {
+    let lists: Vec<Vec<Value>> = lists.iter().map(list_items).collect();
+    let len = lists[0].len();
+    if lists.iter().any(|l| l.len() != len) {
+        panic!(
+            "SHEQ: {} expected lists of the same length, got lengths {:?}",
+            op.name(),
+            lists.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+    }
+    (0..len)
+        .map(|i| lists.iter().map(|l| l[i].clone()).collect())
+        .collect()
+}
+
+// check_callback - panics naming the procedure if op is about to call it with the wrong number of arguments
+fn check_callback(op: PrimOp, f: &Value, n: usize) {
+    let (name, accepts) = match f {
+        Value::CloV(clo) => (
+            format!("{{lambda ({}) ...}}", clo.params.join(" ")),
+            clo.params.len() == n,
+        ),
+        Value::PrimV(p) => {
+            let fixed = p.op.arg_types().len();
+            let accepts = match p.op.rest_type() {
+                Some(_) => n >= fixed,
+                None => n == fixed,
+            };
+            (p.op.name().to_string(), accepts)
+        }
+        Value::HostV(host) => (host.name.clone(), host.arity.accepts(n)),
+        Value::StructOp(op) => (op.name(), op.arity() == n),
+        other => (serialize(other), false),
+    };
+    if !accepts {
+        panic!(
+            "SHEQ: {} cannot call {} with {} argument{}",
+            op.name(),
+            name,
+            n,
+            if n == 1 { "" } else { "s" }
+        );
+    }
+}
+
+// is_key - whether v can be a hash key
+fn is_key(v: &Value) -> bool {
+    matches!(
+        v,
+        Value::Real(_) | Value::Exact(_) | Value::String(_) | Value::Symbol(_) | Value::Boolean(_)
+    )
+}
+
+// hash_entries - the entries of a hash map ordered by key: numbers, strings, symbols, then booleans,
+// so hash-keys and printing do not depend on the order of the map
+fn hash_entries(map: &HashMap<Value, Value>) -> Vec<(&Value, &Value)> {
+    let rank = |k: &Value| match k {
+        Value::Real(_) | Value::Exact(_) => 0,
+        Value::String(_) => 1,
+        Value::Symbol(_) => 2,
+        _ => 3,
+    };
+    let mut entries: Vec<(&Value, &Value)> = map.iter().collect();
+    entries.sort_by(|(a, _), (b, _)| {
+        rank(a).cmp(&rank(b)).then_with(|| match (a, b) {
+            (Value::String(x), Value::String(y)) | (Value::Symbol(x), Value::Symbol(y)) => x.cmp(y),
+            (Value::Boolean(x), Value::Boolean(y)) => x.cmp(y),
+            // 1 and 1.0 are both keys, and +nan.0 is unordered, so ties fall back to the printed form
+            _ => num_cmp(a, b)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| serialize(a).cmp(&serialize(b))),
+        })
+    });
+    entries
+}
+
+// list_items - the elements of an argument already checked to be a proper list
+fn list_items(v: &Value) -> Vec<Value> {
+    v.list_items()
+        .unwrap_or_else(|| panic!("SHEQ: expected a list, got {}", serialize(v)))
+}
+
+// char_slice - the chars from start up to stop, strings are indexed by Unicode scalar value, not byte
+fn char_slice(s: &str, start: usize, stop: usize) -> Option<&str> {
+    if start > stop {
+        return None;
+    }
+    // byte offset of each char boundary, including the end of the string
+    let mut bounds = s.char_indices().map(|(i, _)| i).chain([s.len()]);
+    let from = bounds.nth(start)?;
+    let to = if stop == start {
+        from
+    } else {
+        bounds.nth(stop - start - 1)?
+    };
+    Some(&s[from..to])
+}
+
+// apply_host - checks the arity of a host function and calls it
+fn apply_host(host: &HostV, args: Vec<Value>) -> Value {
+    if !host.arity.accepts(args.len()) {
+        panic!(
+            "SHEQ: Incorrect number of arguments to {}, got {}, expected {}",
+            host.name,
+            args.len(),
+            match host.arity {
+                Arity::Exact(k) => format!("{}", k),
+                Arity::AtLeast(k) => format!("at least {}", k),
             }
+        );
+    }
+    (host.func)(args)
+}
+
+// apply_struct_op - builds, tests or reads a struct instance
+fn apply_struct_op(op: &StructOp, args: Vec<Value>) -> Value {
+    if args.len() != op.arity() {
+        panic!(
+            "SHEQ: Incorrect number of arguments to {}, got {}, expected {}",
+            op.name(),
+            args.len(),
+            op.arity()
+        );
+    }
+    match (op.kind, args.as_slice()) {
+        (StructOpKind::Constructor, _) => Value::StructV(StructV {
+            ty: op.ty.clone(),
+            fields: args,
+        }),
+        (StructOpKind::Predicate, [v]) => {
+            Value::Boolean(matches!(v, Value::StructV(sv) if Rc::ptr_eq(&sv.ty, &op.ty)))
+        }
+        (StructOpKind::Accessor(i), [Value::StructV(sv)]) if Rc::ptr_eq(&sv.ty, &op.ty) => {
+            sv.fields[i].clone()
+        }
+        (StructOpKind::Accessor(_), [other]) => panic!(
+            "SHEQ: {} expected a {}, got {}",
+            op.name(),
+            op.ty.name,
+            serialize(other)
+        ),
+        _ => panic!(
+            "SHEQ: {} was given unchecked arguments {:?}",
+            op.name(),
+            args
+        ),
+    }
+}
+
+// check_prim_args - panics with an arity or type error unless args match what op declares
+fn check_prim_args(op: PrimOp, args: &[Value]) {
+    let types = op.arg_types();
+    let rest = op.rest_type();
+    // wrong arity
+    let arity_ok = match rest {
+        Some(_) => args.len() >= types.len(),
+        None => args.len() == types.len(),
+    };
+    if !arity_ok {
+        panic!("SHEQ: Incorrect number of arguments, got {:?}", args.len());
+    }
+    // correct arity but wrong types
+    let ok = args.iter().enumerate().all(|(i, a)| {
+        match types.get(i).copied().or(rest).unwrap_or(ArgType::Any) {
+            ArgType::Number => matches!(a, Value::Real(_) | Value::Exact(_)),
+            ArgType::Integer => matches!(a, Value::Exact(n) if n.is_integer()),
+            ArgType::String => matches!(a, Value::String(_)),
+            ArgType::Symbol => matches!(a, Value::Symbol(_)),
+            ArgType::Pair => matches!(a, Value::Pair(_)),
+            ArgType::List => a.list_items().is_some(),
+            ArgType::Procedure => matches!(
+                a,
+                Value::CloV(_) | Value::PrimV(_) | Value::HostV(_) | Value::StructOp(_)
+            ),
+            ArgType::HashMap => matches!(a, Value::Hash(_)),
+            ArgType::Key => is_key(a),
+            ArgType::Any => true,
         }
-        op => {
-            panic!("SHEQ: Invalid PrimV op, got {}", op);
+    });
+    if !ok {
+        panic!(
+            "SHEQ: Primv {} expected {}, got {:?}",
+            op.name(),
+            describe_arg_types(types),
+            args
+        );
+    }
+}
+
+// describe_arg_types - e.g. "2 numbers", "string", "1 string and 2 numbers"
+fn describe_arg_types(types: &[ArgType]) -> String {
+    let mut groups: Vec<(ArgType, usize)> = Vec::new();
+    for t in types {
+        match groups.last_mut() {
+            Some((last, n)) if last == t => *n += 1,
+            _ => groups.push((*t, 1)),
         }
     }
+    let describe = |t: ArgType, plural: bool| match (t, plural) {
+        (ArgType::Number, false) => "number",
+        (ArgType::Number, true) => "numbers",
+        (ArgType::Integer, false) => "exact integer",
+        (ArgType::Integer, true) => "exact integers",
+        (ArgType::String, false) => "string",
+        (ArgType::String, true) => "strings",
+        (ArgType::Symbol, false) => "symbol",
+        (ArgType::Symbol, true) => "symbols",
+        (ArgType::Pair, false) => "pair",
+        (ArgType::Pair, true) => "pairs",
+        (ArgType::List, false) => "list",
+        (ArgType::List, true) => "lists",
+        (ArgType::Procedure, false) => "procedure",
+        (ArgType::Procedure, true) => "procedures",
+        (ArgType::HashMap, false) => "hash",
+        (ArgType::HashMap, true) => "hashes",
+        (ArgType::Key, false) => "hash key",
+        (ArgType::Key, true) => "hash keys",
+        (ArgType::Any, false) => "value",
+        (ArgType::Any, true) => "values",
+    };
+    match groups.as_slice() {
+        [(t, 1)] => describe(*t, false).to_string(),
+        _ => groups
+            .iter()
+            .map(|(t, n)| format!("{} {}", n, describe(*t, *n != 1)))
+            .collect::<Vec<_>>()
+            .join(" and "),
+    }
 }
 
 // is_reserved - helper method to check if word is reserved
@@ -386,16 +2034,83 @@ fn get_binding_val(name: &str, env: &Env) -> Value {
     panic!("SHEQ: unbound identifier '{}'", name);
 }
 
-// serialize - takes a Value and returns a serialized String
-fn serialize(v: &Value) -> String {
+// serialize - takes a Value and returns a serialized String, forcing it first if it is a Thunk
+pub fn serialize(v: &Value) -> String {
     match v {
-        Value::Real(n) => format!("{}", n),
+        Value::Thunk(_) => serialize(&force(v.clone())),
+        Value::Real(n) => serialize_real(*n),
+        Value::Exact(n) => n.to_string(),
         Value::Boolean(true) => "true".into(),
         Value::Boolean(false) => "false".into(),
         Value::String(s) => format!("{:?}", s), // :? is same as ~v from Racket
-        Value::CloV(_) => "#<procedure>".into(),
-        Value::PrimV(_) => "#<primop>".into(),
+        Value::Symbol(_) | Value::Pair(_) | Value::Null | Value::Hash(_) => {
+            format!("'{}", serialize_datum(v))
+        }
+        Value::StructV(sv) => {
+            let mut parts = vec![format!("#<struct:{}", sv.ty.name)];
+            parts.extend(sv.fields.iter().map(serialize));
+            format!("{}>", parts.join(" "))
+        }
+        Value::CloV(_) | Value::StructOp(_) => "#<procedure>".into(),
+        Value::PrimV(_) | Value::HostV(_) => "#<primop>".into(),
+        Value::Void => "#<void>".into(),
+    }
+}
+
+// serialize_datum - a list inside a quoted list, nested lists are not quoted again
+fn serialize_datum(v: &Value) -> String {
+    match v {
+        Value::Symbol(name) => name.clone(),
+        Value::Null => "()".into(),
+        Value::Pair(_) => {
+            let mut parts = Vec::new();
+            let mut cur = v;
+            while let Value::Pair(pair) = cur {
+                parts.push(serialize_datum(&pair.first));
+                cur = &pair.rest;
+            }
+            // an improper list ends in a dotted tail
+            if !matches!(cur, Value::Null) {
+                parts.push(".".into());
+                parts.push(serialize_datum(cur));
+            }
+            format!("({})", parts.join(" "))
+        }
+        Value::Hash(map) => {
+            let entries: Vec<String> = hash_entries(map)
+                .into_iter()
+                .map(|(k, v)| serialize_datum(&Value::cons(k.clone(), v.clone())))
+                .collect();
+            format!("#hash({})", entries.join(" "))
+        }
+        other => serialize(other),
+    }
+}
+
+// serialize_real - prints a float the way Racket's ~v does: always with a decimal point or exponent,
+// in scientific notation below 1e-4 and from 1e+21 up with a signed exponent, and +inf.0, -inf.0, +nan.0 for
+// the special values
+pub(crate) fn serialize_real(n: f64) -> String {
+    if n.is_nan() {
+        return "+nan.0".into();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "+inf.0" } else { "-inf.0" }.into();
+    }
+    let abs = n.abs();
+    if abs != 0.0 && !(1e-4..1e21).contains(&abs) {
+        // {:e} gives the shortest round-tripping digits, e.g. 1.5e-7
+        let sci = format!("{:e}", n);
+        let (mantissa, exp) = sci
+            .split_once('e')
+            .expect("SHEQ: {:e} always has an exponent");
+        return match exp.strip_prefix('-') {
+            Some(digits) => format!("{}e-{:0>2}", mantissa, digits),
+            None => format!("{}e+{}", mantissa, exp),
+        };
     }
+    let s = format!("{}", n);
+    if s.contains('.') { s } else { s + ".0" }
 }
 
 // create_env - takes a list of params, list of vals, and an Env to return a new extended Env
@@ -423,6 +2138,120 @@ fn create_env(params: &[String], vals: &[Value], base_env: &Env) -> Env {
     new_env
 }
 
+// top_interp - parses and interprets a SHEQ program in the top env, returning the serialized result
+pub fn top_interp(s: &str) -> String {
+    top_interp_with(s, Config::default())
+}
+
+// top_interp_with - top_interp under the given Config
+pub fn top_interp_with(s: &str, config: Config) -> String {
+    serialize(&interp_with(&parse(&read(s)), &base_env(config), config))
+}
+
+// top_step - parses a SHEQ program and returns every intermediate program of its reduction in the
+// default base_env, unparsed
+pub fn top_step(s: &str) -> Vec<String> {
+    step::steps(&parse(&read(s)), &base_env(Config::default()))
+        .iter()
+        .map(unparse)
+        .collect()
+}
+
+// top_interp_program - parses and interprets a multi-form SHEQ program, returning each expression's result.
+// Its requires are relative to the current directory
+pub fn top_interp_program(s: &str, config: Config) -> Vec<String> {
+    let base = base_env(config);
+    let mut env = base.clone();
+    Modules::new()
+        .run_program(&parse_program(s), Path::new("."), &mut env, &base, config)
+        .iter()
+        .map(serialize)
+        .collect()
+}
+
+// run_file - runs the SHEQ program in the file at path, printing each expression's result.
+// Its requires are relative to the file's directory
+pub fn run_file(path: &str) {
+    run_file_with(path, Config::default())
+}
+
+// run_file_with - run_file under the given Config
+pub fn run_file_with(path: &str, config: Config) {
+    for v in Modules::new().run_file(Path::new(path), &base_env(config), config) {
+        println!("{}", serialize(&v));
+    }
+}
+
+// repl - reads SHEQ programs from stdin and prints their results, definitions persist between inputs
+// :static / :dynamic switch scoping, :strict / :lazy switch argument passing,
+// :step toggles printing every intermediate program, :quit exits
+pub fn repl() {
+    repl_with(Config::default())
+}
+
+// repl_with - repl starting from the given Config
+pub fn repl_with(config: Config) {
+    let mut sheq = Interpreter::with_config(config);
+    let mut stepping = false;
+    let mut buffer = String::new();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{}", if buffer.is_empty() { "> " } else { "  " });
+        io::stdout().flush().expect("SHEQ: could not flush stdout");
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+
+        if buffer.is_empty() && line.trim().starts_with(':') {
+            let mut config = sheq.config();
+            match line.trim() {
+                ":static" => config.scope = Scope::Static,
+                ":dynamic" => config.scope = Scope::Dynamic,
+                ":strict" => config.strategy = Strategy::Strict,
+                ":lazy" => config.strategy = Strategy::Lazy,
+                ":step" => stepping = !stepping,
+                ":quit" => break,
+                other => eprintln!("SHEQ: unknown command {}", other),
+            }
+            sheq.set_config(config);
+            continue;
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if !is_balanced(&buffer) {
+            continue;
+        }
+        let src = std::mem::take(&mut buffer);
+        if src.trim().is_empty() {
+            continue;
+        }
+        if stepping {
+            match sheq.steps(&src) {
+                Ok(programs) => {
+                    for program in programs {
+                        println!("=> {}", unparse(&program));
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        } else {
+            match sheq.eval_program(&src) {
+                // like Racket's REPL, a Void result prints nothing
+                Ok(results) => {
+                    for v in results.iter().filter(|v| **v != Value::Void) {
+                        println!("{}", serialize(v));
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
+
 pub fn run() {
     let env = top_env(); // copy of top_env
 
@@ -448,7 +2277,22 @@ pub fn run() {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    // top_interp - shadows the crate's top_interp so every program these tests run through it is also
+    // compiled and run on the VM, which must give the same result
+    fn top_interp(s: &str) -> String {
+        let result = super::top_interp(s);
+        let vm_result = serialize(&vm::vm_interp(&parse(&read(s))));
+        assert_eq!(result, vm_result, "the VM disagrees with interp on {}", s);
+        result
+    }
+
+    // interp_prim - shadows the crate's interp_prim for applying a primitive outside of any program
+    fn interp_prim(prim: &PrimV, args: Vec<Value>) -> Value {
+        super::interp_prim(prim, args, &top_env(), Config::default())
+    }
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn reserved_keywords_work() {
         assert!(is_reserved("if"));
         assert!(is_reserved("lambda"));
@@ -457,8 +2301,10 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::redundant_field_names)]
     fn serialize_work() {
-        assert_eq!(serialize(&Value::Real(32.0)), "32");
+        assert_eq!(serialize(&Value::Real(32.0)), "32.0");
+        assert_eq!(serialize(&Value::Exact(Rational::from(32))), "32");
         assert_eq!(serialize(&Value::Boolean(true)), "true");
         assert_eq!(serialize(&Value::Boolean(false)), "false");
         assert_eq!(serialize(&Value::String("hello".into())), "\"hello\"");
@@ -471,13 +2317,45 @@ mod tests {
         });
         assert_eq!(serialize(&clo), "#<procedure>");
         assert_eq!(
-            serialize(&Value::PrimV(PrimV {
-                op: "equal?".into()
-            })),
+            serialize(&Value::PrimV(PrimV { op: PrimOp::Equal })),
             "#<primop>"
         );
     }
 
+    #[test]
+    fn serialize_real_matches_racket() {
+        let golden = [
+            (3.0, "3.0"),
+            (-2.5, "-2.5"),
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (0.1, "0.1"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (1.5e-7, "1.5e-07"),
+            (2e-300, "2e-300"),
+            (1e20, "100000000000000000000.0"),
+            (1e21, "1e+21"),
+            (-1.25e22, "-1.25e+22"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (f64::INFINITY, "+inf.0"),
+            (f64::NEG_INFINITY, "-inf.0"),
+            (f64::NAN, "+nan.0"),
+        ];
+        for (n, expected) in golden {
+            assert_eq!(serialize(&Value::Real(n)), expected, "{:?}", n);
+        }
+    }
+
+    #[test]
+    fn special_floats_read_back() {
+        assert_eq!(top_interp("{* 1e200 1e200}"), "+inf.0");
+        assert_eq!(top_interp("{- 0.0 +inf.0}"), "-inf.0");
+        assert_eq!(top_interp("{* 2 1e-05}"), "2e-05");
+        assert_eq!(top_interp("{* 2 1e+21}"), "2e+21");
+    }
+
     #[test]
     fn interp_works() {
         let env = top_env();
@@ -491,7 +2369,7 @@ mod tests {
             ],
         });
 
-        assert_eq!(serialize(&interp(&expr1, &env)), "3");
+        assert_eq!(serialize(&interp(&expr1, &env)), "3.0");
     }
 
     #[test]
@@ -510,6 +2388,7 @@ mod tests {
             })),
             iftrue: Box::new(ExprC::NumC(NumC { n: 1.0 })),
             iffalse: Box::new(ExprC::NumC(NumC { n: 2.0 })),
+            form: IfForm::If,
         });
         let env = top_env();
         assert!(matches!(interp(&expr, &env), Value::Real(1.0)))
@@ -522,6 +2401,7 @@ mod tests {
             v: Box::new(ExprC::NumC(NumC { n: 1.0 })),
             iftrue: Box::new(ExprC::NumC(NumC { n: 1.0 })),
             iffalse: Box::new(ExprC::NumC(NumC { n: 2.0 })),
+            form: IfForm::If,
         });
         let env = top_env();
         let _ = interp(&expr, &env);
@@ -547,7 +2427,7 @@ mod tests {
     }
     #[test]
     fn interp_prim_add() {
-        let prim_add = PrimV { op: "+".into() };
+        let prim_add = PrimV { op: PrimOp::Add };
         let v_add = interp_prim(&prim_add, vec![Value::Real(3.0), Value::Real(10.0)]);
 
         assert!(matches!(v_add, Value::Real(13.0)));
@@ -555,7 +2435,7 @@ mod tests {
 
     #[test]
     fn interp_prim_sub() {
-        let prim_sub = PrimV { op: "-".into() };
+        let prim_sub = PrimV { op: PrimOp::Sub };
         let v_sub = interp_prim(&prim_sub, vec![Value::Real(10.0), Value::Real(2.0)]);
 
         assert!(matches!(v_sub, Value::Real(8.0)));
@@ -564,20 +2444,20 @@ mod tests {
     #[test]
     #[should_panic(expected = "Primv - expected 2 numbers")]
     fn sub_wrong_types() {
-        let prim_swt = PrimV { op: "-".into() };
+        let prim_swt = PrimV { op: PrimOp::Sub };
         let _ = interp_prim(&prim_swt, vec![Value::Boolean(true), Value::Real(2.0)]);
     }
 
     #[test]
     #[should_panic(expected = "Incorrect number of arguments")]
     fn sub_wrong_arity() {
-        let prim_swa = PrimV { op: "-".into() };
+        let prim_swa = PrimV { op: PrimOp::Sub };
         let _ = interp_prim(&prim_swa, vec![Value::Real(1.0)]);
     }
 
     #[test]
     fn interp_prim_mult() {
-        let prim_mult = PrimV { op: "*".into() };
+        let prim_mult = PrimV { op: PrimOp::Mul };
         let v_mult = interp_prim(&prim_mult, vec![Value::Real(4.0), Value::Real(3.0)]);
 
         assert!(matches!(v_mult, Value::Real(12.0)));
@@ -585,7 +2465,7 @@ mod tests {
 
     #[test]
     fn interp_prim_div() {
-        let prim_div = PrimV { op: "/".into() };
+        let prim_div = PrimV { op: PrimOp::Div };
         let v_div = interp_prim(&prim_div, vec![Value::Real(12.0), Value::Real(2.0)]);
 
         assert!(matches!(v_div, Value::Real(6.0)));
@@ -594,13 +2474,13 @@ mod tests {
     #[test]
     #[should_panic(expected = "Divide by zero error")]
     fn div_zero_error() {
-        let prim_dze = PrimV { op: "/".into() };
+        let prim_dze = PrimV { op: PrimOp::Div };
         let _ = interp_prim(&prim_dze, vec![Value::Real(2.0), Value::Real(0.0)]);
     }
 
     #[test]
     fn interp_prim_leq() {
-        let prim_leq = PrimV { op: "<=".into() };
+        let prim_leq = PrimV { op: PrimOp::Leq };
         let v_leq1 = interp_prim(&prim_leq, vec![Value::Real(1.0), Value::Real(2.0)]);
         assert!(matches!(v_leq1, Value::Boolean(true)));
 
@@ -610,9 +2490,7 @@ mod tests {
 
     #[test]
     fn interp_eq() {
-        let prim_eq = PrimV {
-            op: "equal?".into(),
-        };
+        let prim_eq = PrimV { op: PrimOp::Equal };
         let v_eq1 = interp_prim(&prim_eq, vec![Value::Real(3.0), Value::Real(3.0)]);
         assert!(matches!(v_eq1, Value::Boolean(true)));
 
@@ -629,21 +2507,21 @@ mod tests {
     #[test]
     #[should_panic(expected = "Primv <=")]
     fn leq_wrong_types() {
-        let prim_lwt = PrimV { op: "<=".into() };
+        let prim_lwt = PrimV { op: PrimOp::Leq };
         let _ = interp_prim(&prim_lwt, vec![Value::Boolean(true), Value::Real(1.0)]);
     }
 
     #[test]
     fn interp_substr() {
         let prim_substr = PrimV {
-            op: "substring".into(),
+            op: PrimOp::Substring,
         };
         let v_substr = interp_prim(
             &prim_substr,
             vec![
                 Value::String("hello".into()),
-                Value::Real(1.0),
-                Value::Real(4.0),
+                Value::Exact(Rational::from(1)),
+                Value::Exact(Rational::from(4)),
             ],
         );
         assert_eq!(v_substr, Value::String("ell".to_string()));
@@ -653,50 +2531,52 @@ mod tests {
     #[should_panic(expected = "string index out of range")]
     fn substr_idx_range_error() {
         let prim_ire = PrimV {
-            op: "substring".into(),
+            op: PrimOp::Substring,
         };
         let _ = interp_prim(
             &prim_ire,
             vec![
                 Value::String("hi".into()),
-                Value::Real(0.0),
-                Value::Real(5.0),
+                Value::Exact(Rational::from(0)),
+                Value::Exact(Rational::from(5)),
             ],
         );
     }
 
     #[test]
-    #[should_panic(expected = "Primv substring expected 1 string and 2 numbers")]
+    #[should_panic(expected = "Primv substring expected 1 string and 2 exact integers")]
     fn substr_wrong_types() {
         let prim_stwt = PrimV {
-            op: "substring".into(),
+            op: PrimOp::Substring,
         };
         let _ = interp_prim(
             &prim_stwt,
-            vec![Value::Boolean(true), Value::Real(0.0), Value::Real(5.0)],
+            vec![
+                Value::Boolean(true),
+                Value::Exact(Rational::from(0)),
+                Value::Exact(Rational::from(5)),
+            ],
         );
     }
 
     #[test]
     fn interp_strl() {
-        let prim_strl = PrimV {
-            op: "strlen".into(),
-        };
+        let prim_strl = PrimV { op: PrimOp::Strlen };
         let v_strl = interp_prim(&prim_strl, vec![Value::String("hello".into())]);
-        assert_eq!(v_strl, Value::Real(5.0));
+        assert_eq!(v_strl, Value::Exact(Rational::from(5)));
     }
 
     #[test]
     #[should_panic(expected = "Primv error expected string")]
     fn interp_prim_error_type() {
-        let prim_et = PrimV { op: "error".into() };
+        let prim_et = PrimV { op: PrimOp::Error };
         let _ = interp_prim(&prim_et, vec![Value::Real(1.0)]);
     }
 
     #[test]
     #[should_panic(expected = "Incorrect number of arguments")]
     fn interp_prim_error_arity() {
-        let prim_ea = PrimV { op: "error".into() };
+        let prim_ea = PrimV { op: PrimOp::Error };
         let _ = interp_prim(&prim_ea, vec![]);
     }
 
@@ -726,5 +2606,1120 @@ mod tests {
         });
         let _ = interp(&expr, &env);
     }
-}
+    #[test]
+    fn lazy_skips_unused_args() {
+        let env = top_env();
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        // ((lambda (x) 1) (error "boom"))
+        let expr = ExprC::AppC(AppC {
+            expr: Box::new(ExprC::LamC(LamC {
+                args: vec!["x".into()],
+                body: Box::new(ExprC::NumC(NumC { n: 1.0 })),
+            })),
+            args: vec![Box::new(ExprC::AppC(AppC {
+                expr: Box::new(ExprC::IdC(IdC {
+                    name: "error".into(),
+                })),
+                args: vec![Box::new(ExprC::StringC(StringC { s: "boom".into() }))],
+            }))],
+        });
+        assert_eq!(serialize(&interp_with(&expr, &env, lazy)), "1.0");
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: boom")]
+    fn strict_evaluates_unused_args() {
+        let env = top_env();
+        let expr = ExprC::AppC(AppC {
+            expr: Box::new(ExprC::LamC(LamC {
+                args: vec!["x".into()],
+                body: Box::new(ExprC::NumC(NumC { n: 1.0 })),
+            })),
+            args: vec![Box::new(ExprC::AppC(AppC {
+                expr: Box::new(ExprC::IdC(IdC {
+                    name: "error".into(),
+                })),
+                args: vec![Box::new(ExprC::StringC(StringC { s: "boom".into() }))],
+            }))],
+        });
+        let _ = interp(&expr, &env);
+    }
+
+    #[test]
+    fn lazy_matches_strict() {
+        let env = top_env();
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        // ((lambda (x) (if (<= x 3) (* x x) x)) (+ 1 2))
+        let expr = ExprC::AppC(AppC {
+            expr: Box::new(ExprC::LamC(LamC {
+                args: vec!["x".into()],
+                body: Box::new(ExprC::IfC(IfC {
+                    v: Box::new(ExprC::AppC(AppC {
+                        expr: Box::new(ExprC::IdC(IdC { name: "<=".into() })),
+                        args: vec![
+                            Box::new(ExprC::IdC(IdC { name: "x".into() })),
+                            Box::new(ExprC::NumC(NumC { n: 3.0 })),
+                        ],
+                    })),
+                    iftrue: Box::new(ExprC::AppC(AppC {
+                        expr: Box::new(ExprC::IdC(IdC { name: "*".into() })),
+                        args: vec![
+                            Box::new(ExprC::IdC(IdC { name: "x".into() })),
+                            Box::new(ExprC::IdC(IdC { name: "x".into() })),
+                        ],
+                    })),
+                    iffalse: Box::new(ExprC::IdC(IdC { name: "x".into() })),
+                    form: IfForm::If,
+                })),
+            })),
+            args: vec![Box::new(ExprC::AppC(AppC {
+                expr: Box::new(ExprC::IdC(IdC { name: "+".into() })),
+                args: vec![
+                    Box::new(ExprC::NumC(NumC { n: 1.0 })),
+                    Box::new(ExprC::NumC(NumC { n: 2.0 })),
+                ],
+            }))],
+        });
+        assert_eq!(serialize(&interp(&expr, &env)), "9.0");
+        assert_eq!(serialize(&interp_with(&expr, &env, lazy)), "9.0");
+    }
 
+    #[test]
+    fn force_memoizes() {
+        let env = top_env();
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        let expr = ExprC::AppC(AppC {
+            expr: Box::new(ExprC::IdC(IdC { name: "+".into() })),
+            args: vec![
+                Box::new(ExprC::NumC(NumC { n: 1.0 })),
+                Box::new(ExprC::NumC(NumC { n: 2.0 })),
+            ],
+        });
+        let thunk = delay(&expr, &env, lazy);
+        assert_eq!(force(thunk.clone()), Value::Real(3.0));
+        match thunk {
+            Value::Thunk(t) => {
+                assert!(matches!(
+                    *t.state.borrow(),
+                    ThunkState::Forced(Value::Real(3.0))
+                ))
+            }
+            other => panic!("SHEQ: Expected thunk, got {:?}", other),
+        }
+    }
+    // the classic counterexample: f's x is 1 under static scope, but the caller's x under dynamic scope
+    const SCOPE_PROGRAM: &str = "{let [x = 1] in
+                                   {let [f = {lambda (y) {+ x y}}] in
+                                     {let [x = 100] in {f 1} end} end} end}";
+
+    #[test]
+    fn static_scope() {
+        assert_eq!(top_interp(SCOPE_PROGRAM), "2");
+    }
+
+    #[test]
+    fn dynamic_scope() {
+        let dynamic = Config {
+            scope: Scope::Dynamic,
+            ..Config::default()
+        };
+        assert_eq!(top_interp_with(SCOPE_PROGRAM, dynamic), "101");
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'y'")]
+    fn static_scope_unbound() {
+        // y is only bound at the call site, which static scope cannot see
+        let _ = top_interp("{let [f = {lambda () y}] in {let [y = 2] in {f} end} end}");
+    }
+
+    #[test]
+    fn dynamic_scope_sees_caller() {
+        let dynamic = Config {
+            scope: Scope::Dynamic,
+            ..Config::default()
+        };
+        assert_eq!(
+            top_interp_with(
+                "{let [f = {lambda () y}] in {let [y = 2] in {f} end} end}",
+                dynamic
+            ),
+            "2"
+        );
+    }
+    #[test]
+    fn prim_ops_bound_in_top_env() {
+        let env = top_env();
+        for op in PrimOp::ALL {
+            assert_eq!(get_binding_val(op.name(), &env), Value::PrimV(PrimV { op }));
+        }
+    }
+
+    #[test]
+    fn describe_arg_types_works() {
+        assert_eq!(describe_arg_types(PrimOp::Add.arg_types()), "2 numbers");
+        assert_eq!(describe_arg_types(PrimOp::Strlen.arg_types()), "string");
+        assert_eq!(
+            describe_arg_types(PrimOp::Substring.arg_types()),
+            "1 string and 2 exact integers"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Incorrect number of arguments, got 2")]
+    fn strlen_wrong_arity() {
+        let prim = PrimV { op: PrimOp::Strlen };
+        let _ = interp_prim(
+            &prim,
+            vec![Value::String("a".into()), Value::String("b".into())],
+        );
+    }
+    fn host_registry() -> Registry {
+        let mut registry = Registry::new();
+        registry
+            .register("string-repeat", Arity::Exact(2), |args| {
+                match (String::from_value(&args[0]), usize::from_value(&args[1])) {
+                    (Ok(s), Ok(n)) => Value::String(s.repeat(n)),
+                    _ => panic!(
+                        "SHEQ: string-repeat expected a string and a count, got {:?}",
+                        args
+                    ),
+                }
+            })
+            .register("sum", Arity::AtLeast(0), |args| {
+                Value::Real(args.iter().fold(0.0, |acc, a| match f64::from_value(a) {
+                    Ok(n) => acc + n,
+                    Err(e) => panic!("SHEQ: sum {}", e),
+                }))
+            });
+        registry
+    }
+
+    #[test]
+    fn host_fns_callable_like_prims() {
+        let env = host_registry().env();
+        let call = |src: &str| serialize(&interp(&parse(&read(src)), &env));
+        assert_eq!(call("{string-repeat \"ab\" 3}"), "\"ababab\"");
+        assert_eq!(call("{sum 1 2 3 4}"), "10.0");
+        assert_eq!(call("{{lambda (f) {f}} sum}"), "0.0");
+        assert_eq!(call("sum"), "#<primop>");
+        assert_eq!(call("{equal? sum sum}"), "true");
+    }
+
+    #[test]
+    #[should_panic(expected = "Incorrect number of arguments to string-repeat, got 1, expected 2")]
+    fn host_fn_wrong_arity() {
+        let env = host_registry().env();
+        let _ = interp(&parse(&read("{string-repeat \"ab\"}")), &env);
+    }
+
+    #[test]
+    fn host_fns_run_on_vm() {
+        let e = parse(&read("{{lambda (x) {sum x x x}} 2}"));
+        let program = vm::compile(&e, host_registry().env());
+        assert_eq!(vm::run(&program), Value::Real(6.0));
+    }
+    #[test]
+    fn definitions_are_freed_with_their_env() {
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        for config in [Config::default(), lazy] {
+            let mut env = top_env();
+            let program = "{define {count n} {if {<= n 0} 0 {count {- n 1}}}}
+                           {define {even? n} {if {<= n 0} true {odd? {- n 1}}}}
+                           {define {odd? n} {if {<= n 0} false {even? {- n 1}}}}
+                           {count 20}
+                           {odd? 7}";
+            let results = interp_program(&parse_program(program), &mut env, config);
+            assert_eq!(results[1], Value::Boolean(true));
+            let cells: Vec<Weak<RefCell<ThunkState>>> = env[env.len() - 3..]
+                .iter()
+                .map(|b| match &*b.val {
+                    Value::Thunk(thunk) => Rc::downgrade(&thunk.state),
+                    other => panic!("SHEQ: expected a definition Thunk, got {:?}", other),
+                })
+                .collect();
+            for _ in 0..10 {
+                let results =
+                    interp_program(&parse_program("{count 20}"), &mut env.clone(), config);
+                assert_eq!(results, vec![Value::Exact(Rational::from(0))]);
+            }
+            // nothing but env held the definitions, their closures do not keep themselves alive
+            drop(results);
+            drop(env);
+            assert!(cells.iter().all(|cell| cell.upgrade().is_none()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "count is no longer defined")]
+    fn closure_outliving_its_definitions() {
+        let mut env = top_env();
+        let program = "{define {count n} {if {<= n 0} 0 {count {- n 1}}}} count";
+        let count = interp_program(&parse_program(program), &mut env, Config::default()).remove(0);
+        drop(env);
+        let _ = apply_value(
+            count,
+            vec![Value::Exact(Rational::from(2))],
+            &top_env(),
+            Config::default(),
+        );
+    }
+
+    #[test]
+    fn program_defines_are_mutually_recursive() {
+        let program = "{define {even? n} {if {<= n 0} true {odd? {- n 1}}}}
+                       {define {odd? n} {if {<= n 0} false {even? {- n 1}}}}
+                       {even? 10}
+                       {odd? 10}";
+        assert_eq!(
+            top_interp_program(program, Config::default()),
+            vec!["true", "false"]
+        );
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        assert_eq!(top_interp_program(program, lazy), vec!["true", "false"]);
+    }
+
+    #[test]
+    fn program_prints_each_expression() {
+        let program = "{define x 2}
+                       {define square {lambda (n) {* n n}}}
+                       {square x}
+                       \"done\"";
+        assert_eq!(
+            top_interp_program(program, Config::default()),
+            vec!["4", "\"done\""]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "y used before its definition")]
+    fn define_used_before_definition() {
+        let _ = top_interp_program("{define x {+ y 1}} {define y 1}", Config::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate definition of x")]
+    fn define_duplicate() {
+        let _ = top_interp_program("{define x 1} {define x 2}", Config::default());
+    }
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(top_interp("{/ 1 3}"), "1/3");
+        assert_eq!(top_interp("{+ 1/3 2/3}"), "1");
+        assert_eq!(
+            top_interp("{* 99999999999 99999999999}"),
+            "9999999999800000000001"
+        );
+        assert_eq!(top_interp("{- 1/2 1}"), "-1/2");
+        assert_eq!(top_interp("{<= 1/3 0.3333}"), "false");
+        assert_eq!(top_interp("{equal? 1/2 2/4}"), "true");
+    }
+
+    #[test]
+    fn comparison_prims() {
+        assert_eq!(top_interp("{< 1 2}"), "true");
+        assert_eq!(top_interp("{< 2 2}"), "false");
+        assert_eq!(top_interp("{> 1/2 0.4}"), "true");
+        assert_eq!(top_interp("{>= 2 2.0}"), "true");
+        assert_eq!(top_interp("{= 1 1.0}"), "true");
+        assert_eq!(top_interp("{= 1/3 0.3333333333333333}"), "false");
+        assert_eq!(top_interp("{not false}"), "true");
+        assert_eq!(top_interp("{not 0}"), "false");
+    }
+
+    #[test]
+    fn arithmetic_prims() {
+        assert_eq!(top_interp("{abs -5/2}"), "5/2");
+        assert_eq!(top_interp("{abs -1.5}"), "1.5");
+        assert_eq!(top_interp("{min 1 2}"), "1");
+        assert_eq!(top_interp("{max 1 2.0}"), "2.0");
+        assert_eq!(top_interp("{quotient -7 2}"), "-3");
+        assert_eq!(top_interp("{remainder -7 2}"), "-1");
+        assert_eq!(top_interp("{modulo -7 2}"), "1");
+        assert_eq!(top_interp("{modulo 7 -2}"), "-1");
+        assert_eq!(top_interp("{modulo -7.0 2}"), "1.0");
+        assert_eq!(top_interp("{floor -7/2}"), "-4");
+        assert_eq!(top_interp("{ceiling 3.2}"), "4.0");
+        assert_eq!(top_interp("{round 5/2}"), "2");
+        assert_eq!(top_interp("{round 3.5}"), "4.0");
+        assert_eq!(top_interp("{sqrt 16/9}"), "4/3");
+        assert_eq!(top_interp("{sqrt 2}"), "1.4142135623730951");
+        assert_eq!(
+            top_interp("{expt 2 100}"),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(top_interp("{expt 2 -2}"), "1/4");
+        assert_eq!(top_interp("{expt 4 0.5}"), "2.0");
+        assert_eq!(top_interp("{exp 0}"), "1");
+        assert_eq!(top_interp("{log 1}"), "0");
+        assert_eq!(top_interp("{log 0.0}"), "-inf.0");
+        assert_eq!(top_interp("{sin 0}"), "0");
+        assert_eq!(top_interp("{cos 0.0}"), "1.0");
+    }
+
+    #[test]
+    #[should_panic(expected = "sqrt expected a non-negative number, got -4")]
+    fn sqrt_negative_error() {
+        let _ = top_interp("{sqrt -4}");
+    }
+
+    #[test]
+    #[should_panic(expected = "quotient expected integers, got 7/2 and 2")]
+    fn quotient_non_integer_error() {
+        let _ = top_interp("{quotient 7/2 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Divide by zero error")]
+    fn modulo_zero_error() {
+        let _ = top_interp("{modulo 7 0}");
+    }
+
+    #[test]
+    #[should_panic(expected = "log is undefined for exact 0")]
+    fn log_zero_error() {
+        let _ = top_interp("{log 0}");
+    }
+
+    #[test]
+    fn expt_of_unit_bases() {
+        assert_eq!(top_interp("{expt 1 100000000000000000000}"), "1");
+        assert_eq!(top_interp("{expt -1 1000000001}"), "-1");
+        assert_eq!(top_interp("{expt -1 -1000000000}"), "1");
+        assert_eq!(top_interp("{expt 0 1000000000}"), "0");
+    }
+
+    #[test]
+    #[should_panic(expected = "expt result is too large to be exact, got 2 and 1000000000")]
+    fn expt_exact_too_large() {
+        let _ = top_interp("{expt 2 1000000000}");
+    }
+
+    #[test]
+    #[should_panic(expected = "expt of a negative number needs an integer power")]
+    fn expt_negative_fraction_error() {
+        let _ = top_interp("{expt -8 1/3}");
+    }
+
+    #[test]
+    fn strings_count_chars() {
+        assert_eq!(top_interp("{strlen \"héllo\"}"), "5");
+        assert_eq!(top_interp("{strlen \"日本語\"}"), "3");
+        assert_eq!(top_interp("{strlen \"🦀!\"}"), "2");
+        assert_eq!(top_interp("{substring \"héllo\" 1 3}"), "\"él\"");
+        assert_eq!(top_interp("{substring \"日本語\" 2 3}"), "\"語\"");
+        assert_eq!(top_interp("{substring \"日本語\" 3 3}"), "\"\"");
+        assert_eq!(top_interp("{substring \"🦀!\" 0 1}"), "\"🦀\"");
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: string index out of range")]
+    fn substr_multibyte_out_of_range() {
+        // 3 is within the 6 bytes of 日本 but past its 2 chars
+        let _ = top_interp("{substring \"日本\" 1 3}");
+    }
+
+    #[test]
+    fn string_library() {
+        assert_eq!(top_interp("{string-append \"ab\" \"cé\"}"), "\"abcé\"");
+        assert_eq!(top_interp("{string-ref \"héllo\" 1}"), "\"é\"");
+        assert_eq!(top_interp("{string=? \"a\" \"a\"}"), "true");
+        assert_eq!(top_interp("{string<? \"apple\" \"banana\"}"), "true");
+        assert_eq!(top_interp("{string<? \"b\" \"a\"}"), "false");
+        assert_eq!(top_interp("{string-upcase \"straße\"}"), "\"STRASSE\"");
+        assert_eq!(top_interp("{string-downcase \"ÀB\"}"), "\"àb\"");
+        assert_eq!(top_interp("{string-contains? \"hello\" \"ell\"}"), "true");
+        assert_eq!(top_interp("{string-contains? \"hello\" \"le\"}"), "false");
+        assert_eq!(top_interp("{number->string 1/3}"), "\"1/3\"");
+        assert_eq!(top_interp("{number->string 2.0}"), "\"2.0\"");
+        assert_eq!(top_interp("{string->number \"42\"}"), "42");
+        assert_eq!(top_interp("{string->number \"-1.5e3\"}"), "-1500.0");
+        assert_eq!(top_interp("{string->number \"forty\"}"), "false");
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: string index out of range")]
+    fn string_ref_out_of_range() {
+        let _ = top_interp("{string-ref \"hé\" 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv string-append expected 2 strings")]
+    fn string_append_wrong_types() {
+        let _ = top_interp("{string-append \"a\" 1}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Incorrect number of arguments, got 1")]
+    fn string_contains_wrong_arity() {
+        let _ = top_interp("{string-contains? \"a\"}");
+    }
+
+    #[test]
+    fn list_prims() {
+        assert_eq!(top_interp("{list 1 2 3}"), "'(1 2 3)");
+        assert_eq!(top_interp("{list}"), "'()");
+        assert_eq!(top_interp("empty"), "'()");
+        assert_eq!(top_interp("{cons 1 {cons \"a\" empty}}"), "'(1 \"a\")");
+        assert_eq!(top_interp("{cons 1 2}"), "'(1 . 2)");
+        assert_eq!(
+            top_interp("{list {list 1 2} empty true}"),
+            "'((1 2) () true)"
+        );
+        assert_eq!(top_interp("{first {list 1 2}}"), "1");
+        assert_eq!(top_interp("{rest {list 1 2}}"), "'(2)");
+        assert_eq!(top_interp("{empty? empty}"), "true");
+        assert_eq!(top_interp("{empty? {list 1}}"), "false");
+        assert_eq!(top_interp("{cons? {list 1}}"), "true");
+        assert_eq!(top_interp("{length {list 1 2 3}}"), "3");
+        assert_eq!(
+            top_interp("{equal? {list 1 {list 2}} {cons 1 {cons {list 2} empty}}}"),
+            "true"
+        );
+        assert_eq!(top_interp("{equal? {list 1 2} {list 1 2.0}}"), "false");
+    }
+
+    #[test]
+    fn long_lists_compare_and_drop() {
+        let long = |last: f64| {
+            let mut items: Vec<Value> = (0..1_000_000).map(|i| Value::Real(i as f64)).collect();
+            items.push(Value::Real(last));
+            Value::list(items)
+        };
+        let a = long(0.0);
+        assert_eq!(a, long(0.0));
+        assert_ne!(a, long(1.0));
+        // a shared tail outlives the head that is dropped
+        let Value::Pair(head) = &a else {
+            panic!("expected a list");
+        };
+        let tail = head.rest.clone();
+        drop(a);
+        assert_eq!(tail.list_items().map(|items| items.len()), Some(1_000_000));
+        assert_eq!(
+            top_interp_program(
+                "{define l {build-list 50000 {lambda (i) i}}} {length l}",
+                Config::default()
+            ),
+            vec!["50000"]
+        );
+    }
+
+    #[test]
+    fn recursive_list_functions() {
+        let src = "{define {sum l} {if {empty? l} 0 {+ {first l} {sum {rest l}}}}}
+                   {define {squares l} {if {empty? l} empty {cons {* {first l} {first l}} {squares {rest l}}}}}
+                   {sum {squares {list 1 2 3}}}";
+        assert_eq!(top_interp_program(src, Config::default()), vec!["14"]);
+    }
+
+    #[test]
+    fn string_split_join() {
+        assert_eq!(
+            top_interp("{string-split \"a,b,,c,\" \",\"}"),
+            "'(\"a\" \"b\" \"\" \"c\")"
+        );
+        assert_eq!(top_interp("{string-split \"\" \",\"}"), "'()");
+        assert_eq!(
+            top_interp("{string-join {list \"a\" \"b\"} \", \"}"),
+            "\"a, b\""
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv first expected pair")]
+    fn first_of_empty() {
+        let _ = top_interp("{first empty}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv length expected list")]
+    fn length_improper() {
+        let _ = top_interp("{length {cons 1 2}}");
+    }
+
+    #[test]
+    #[should_panic(expected = "string-join expected a list of strings, got 1")]
+    fn string_join_non_strings() {
+        let _ = top_interp("{string-join {list \"a\" 1} \"\"}");
+    }
+
+    #[test]
+    fn higher_order_prims() {
+        assert_eq!(
+            top_interp("{map {lambda (x) {* x x}} {list 1 2 3}}"),
+            "'(1 4 9)"
+        );
+        assert_eq!(top_interp("{map + {list 1 2} {list 10 20}}"), "'(11 22)");
+        assert_eq!(
+            top_interp("{filter {lambda (x) {< x 3}} {list 1 5 2 4}}"),
+            "'(1 2)"
+        );
+        assert_eq!(top_interp("{foldl cons empty {list 1 2 3}}"), "'(3 2 1)");
+        assert_eq!(top_interp("{foldr cons empty {list 1 2 3}}"), "'(1 2 3)");
+        assert_eq!(top_interp("{foldl - 0 {list 1 2 3}}"), "2");
+        assert_eq!(
+            top_interp("{apply string-append {list \"a\" \"b\"}}"),
+            "\"ab\""
+        );
+        assert_eq!(top_interp("{apply list {list 1 2}}"), "'(1 2)");
+        assert_eq!(
+            top_interp("{build-list 4 {lambda (i) {* i 10}}}"),
+            "'(0 10 20 30)"
+        );
+        assert_eq!(top_interp("{build-list 0 +}"), "'()");
+    }
+
+    #[test]
+    fn higher_order_prims_respect_config() {
+        let dynamic = Config {
+            scope: Scope::Dynamic,
+            ..Config::default()
+        };
+        // under dynamic scope the callback sees k from the caller of map, even through apply
+        let src = "{let [f = {lambda (x) {+ x k}}] in {{lambda (k) {map f {list 1 2}}} 10} end}";
+        assert_eq!(top_interp_with(src, dynamic), "'(11 12)");
+        let src = "{let [f = {lambda (x) {+ x k}}]
+                    in {{lambda (k) {apply map {list f {list 1 2}}}} 10} end}";
+        assert_eq!(top_interp_with(src, dynamic), "'(11 12)");
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        assert_eq!(
+            top_interp_with("{map {lambda (x) {+ x 1}} {list 1 2}}", lazy),
+            "'(2 3)"
+        );
+        // under lazy evaluation the callback never forces the argument it ignores
+        let src = "{build-list 2 {lambda (i) {{lambda (unused) i} {/ 1 0}}}}";
+        assert_eq!(top_interp_with(src, lazy), "'(0 1)");
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'k'")]
+    fn higher_order_prims_static_callback() {
+        let _ = top_interp(
+            "{let [f = {lambda (x) {+ x k}}] in {{lambda (k) {map f {list 1 2}}} 10} end}",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: map cannot call {lambda (x y) ...} with 1 argument")]
+    fn map_callback_arity() {
+        let _ = top_interp("{map {lambda (x y) x} {list 1 2}}");
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: foldl cannot call strlen with 2 arguments")]
+    fn foldl_prim_callback_arity() {
+        let _ = top_interp("{foldl strlen 0 {list \"a\"}}");
+    }
+
+    #[test]
+    #[should_panic(expected = "map expected lists of the same length")]
+    fn map_uneven_lists() {
+        let _ = top_interp("{map + {list 1 2} {list 1}}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv filter expected 1 procedure and 1 list")]
+    fn filter_wrong_types() {
+        let _ = top_interp("{filter 1 {list 1}}");
+    }
+
+    #[test]
+    fn define_struct() {
+        let src = "{define-struct posn (x y)}
+                   {define p {make-posn 1 {list 2}}}
+                   p
+                   {posn-x p}
+                   {posn-y p}
+                   {posn? p}
+                   {posn? 5}
+                   {equal? p {make-posn 1 {list 2}}}
+                   {equal? p {make-posn 1 2}}
+                   make-posn";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec![
+                "#<struct:posn 1 '(2)>",
+                "1",
+                "'(2)",
+                "true",
+                "false",
+                "true",
+                "false",
+                "#<procedure>"
+            ]
+        );
+    }
+
+    #[test]
+    fn struct_trees() {
+        let src = "{define-struct leaf (n)}
+                   {define-struct node (left right)}
+                   {define {total t} {if {leaf? t} {leaf-n t} {+ {total {node-left t}} {total {node-right t}}}}}
+                   {total {make-node {make-leaf 1} {make-node {make-leaf 2} {make-leaf 3}}}}
+                   {map leaf-n {list {make-leaf 4} {make-leaf 5}}}";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec!["6", "'(4 5)"]
+        );
+    }
+
+    #[test]
+    fn struct_types_are_distinct() {
+        // two definitions with the same shape make different types
+        let src = "{define-struct a (x)} {define-struct b (x)} {equal? {make-a 1} {make-b 1}} {a? {make-b 1}}";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec!["false", "false"]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: posn-x expected a posn, got 5")]
+    fn struct_accessor_wrong_type() {
+        let _ = top_interp_program("{define-struct posn (x y)} {posn-x 5}", Config::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Incorrect number of arguments to make-posn, got 1, expected 2")]
+    fn struct_constructor_arity() {
+        let _ = top_interp_program(
+            "{define-struct posn (x y)} {make-posn 1}",
+            Config::default(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate definition of posn-x")]
+    fn struct_duplicate_definition() {
+        let _ = top_interp_program(
+            "{define-struct posn (x y)} {define {posn-x p} 1}",
+            Config::default(),
+        );
+    }
+
+    #[test]
+    fn match_literals_and_ids() {
+        let classify = |v: &str| {
+            top_interp(&format!(
+                "{{match {} [0 \"zero\"] [1.5 \"float\"] [\"hi\" \"greeting\"] [true \"yes\"] [empty \"none\"] [x #:when {{< x 0}} \"negative\"] [_ \"other\"]}}",
+                v
+            ))
+        };
+        assert_eq!(classify("0"), "\"zero\"");
+        assert_eq!(classify("1.5"), "\"float\"");
+        assert_eq!(classify("\"hi\""), "\"greeting\"");
+        assert_eq!(classify("true"), "\"yes\"");
+        assert_eq!(classify("empty"), "\"none\"");
+        assert_eq!(classify("-3"), "\"negative\"");
+        assert_eq!(classify("7"), "\"other\"");
+        assert_eq!(classify("0.0"), "\"other\"");
+        assert_eq!(top_interp("{match {+ 1 2} [n {* n n}]}"), "9");
+    }
+
+    #[test]
+    fn match_lists() {
+        let src = "{define {sum l} {match l [empty 0] [{cons x xs} {+ x {sum xs}}]}}
+                   {sum {list 1 2 3}}
+                   {match {list 1 {list 2 3}} [{list a {list b c}} {list c b a}]}
+                   {match {list 1 2} [{list a} a] [{list a b c} a] [_ \"no\"]}";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec!["6", "'(3 2 1)", "\"no\""]
+        );
+    }
+
+    #[test]
+    fn match_structs() {
+        let src = "{define-struct leaf (n)}
+                   {define-struct node (left right)}
+                   {define {total t} {match t [{leaf n} n] [{node l r} {+ {total l} {total r}}]}}
+                   {total {make-node {make-leaf 1} {make-node {make-leaf 2} {make-leaf 3}}}}
+                   {match {make-leaf 5} [{node _ _} \"node\"] [{leaf n} #:when {< n 3} \"small\"] [{leaf _} \"big\"]}";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec!["6", "\"big\""]
+        );
+    }
+
+    #[test]
+    fn match_binds_like_closures() {
+        // a pattern id shadows an outer binding only inside its clause
+        let src = "{let [x = 1] in {+ {match 10 [x x]} x} end}";
+        assert_eq!(top_interp(src), "11");
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        assert_eq!(
+            top_interp_with("{match {list 1 2} [{list a b} {+ a b}]}", lazy),
+            "3"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: no match clause matched '(1 2)")]
+    fn match_not_exhaustive() {
+        let _ = top_interp("{match {list 1 2} [empty 0] [{list x} x]}");
+    }
+
+    #[test]
+    #[should_panic(expected = "match pattern names no struct, got posn")]
+    fn match_unknown_struct() {
+        let _ = top_interp("{match 1 [{posn x y} x]}");
+    }
+
+    #[test]
+    #[should_panic(expected = "match guard expected boolean, got 1")]
+    fn match_guard_not_boolean() {
+        let _ = top_interp("{match 1 [x #:when 1 x]}");
+    }
+
+    #[test]
+    fn hash_prims() {
+        let h = "{hash-set {hash \"b\" 2 1 \"one\"} true {list 3}}";
+        assert_eq!(top_interp(h), "'#hash((1 . \"one\") (\"b\" . 2) (true 3))");
+        assert_eq!(top_interp("{hash}"), "'#hash()");
+        assert_eq!(
+            top_interp(&format!("{{hash-keys {}}}", h)),
+            "'(1 \"b\" true)"
+        );
+        assert_eq!(top_interp(&format!("{{hash-ref {} 1}}", h)), "\"one\"");
+        assert_eq!(top_interp(&format!("{{hash-ref {} 1.0 0}}", h)), "0");
+        assert_eq!(
+            top_interp(&format!("{{hash-ref {} 2 {{lambda () 7}}}}", h)),
+            "7"
+        );
+        assert_eq!(
+            top_interp(&format!("{{hash-has-key? {} \"b\"}}", h)),
+            "true"
+        );
+        assert_eq!(
+            top_interp(&format!("{{hash-keys {{hash-remove {} \"b\"}}}}", h)),
+            "'(1 true)"
+        );
+        assert_eq!(top_interp("{hash-ref {hash 1 \"a\" 1 \"b\"} 1}"), "\"b\"");
+        assert_eq!(
+            top_interp("{equal? {hash 1 2 3 4} {hash-set {hash 3 4} 1 2}}"),
+            "true"
+        );
+    }
+
+    #[test]
+    fn hash_is_immutable() {
+        let src = "{define h {hash \"a\" 1}}
+                   {define h2 {hash-set {hash-remove h \"a\"} \"b\" 2}}
+                   {list h h2}";
+        assert_eq!(
+            top_interp_program(src, Config::default()),
+            vec!["'(#hash((\"a\" . 1)) #hash((\"b\" . 2)))"]
+        );
+    }
+
+    #[test]
+    fn real_equality_and_hashing() {
+        use std::collections::hash_map::DefaultHasher;
+        let nan = "{- {* 1e200 1e200} {* 1e200 1e200}}";
+        assert_eq!(top_interp(&format!("{{equal? {} {}}}", nan, nan)), "true");
+        assert_eq!(top_interp("{equal? 0.0 {* -1.0 0.0}}"), "false");
+        assert_eq!(
+            top_interp(&format!("{{hash-ref {{hash {} 1}} {}}}", nan, nan)),
+            "1"
+        );
+        let hash = |v: &Value| {
+            let mut h = DefaultHasher::new();
+            v.hash(&mut h);
+            h.finish()
+        };
+        let a = Value::list(vec![Value::Real(f64::NAN), Value::String("x".into())]);
+        let b = Value::list(vec![Value::Real(-f64::NAN), Value::String("x".into())]);
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv hash-set expected 1 hash and 1 hash key and 1 value")]
+    fn hash_key_must_be_simple() {
+        let _ = top_interp("{hash-set {hash} {list 1} 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "hash expected an even number of arguments, got 3")]
+    fn hash_odd_arguments() {
+        let _ = top_interp("{hash 1 2 3}");
+    }
+
+    #[test]
+    #[should_panic(expected = "hash-ref found no value for key \"c\"")]
+    fn hash_ref_missing_key() {
+        let _ = top_interp("{hash-ref {hash \"a\" 1} \"c\"}");
+    }
+
+    #[test]
+    fn quote_and_symbols() {
+        assert_eq!(top_interp("'x"), "'x");
+        assert_eq!(top_interp("{quote x}"), "'x");
+        assert_eq!(top_interp("'(1 \"a\" (b) ())"), "'(1 \"a\" (b) ())");
+        // true and empty are only symbols inside a quote
+        assert_eq!(top_interp("{symbol? {first '(true)}}"), "true");
+        assert_eq!(top_interp("{empty? 'empty}"), "false");
+        assert_eq!(top_interp("{symbol? \"x\"}"), "false");
+        assert_eq!(top_interp("{symbol->string 'lambda}"), "\"lambda\"");
+        assert_eq!(top_interp("{equal? 'a {first '(a)}}"), "true");
+        assert_eq!(top_interp("{equal? 'a \"a\"}"), "false");
+        assert_eq!(top_interp("''a"), "'(quote a)");
+        assert_eq!(
+            top_interp("{hash-keys {hash 'b 1 \"a\" 2 'a 3}}"),
+            "'(\"a\" a b)"
+        );
+    }
+
+    #[test]
+    fn quasiquote() {
+        assert_eq!(top_interp("`(1 ,{+ 1 1} 3)"), "'(1 2 3)");
+        assert_eq!(
+            top_interp("{{lambda (op) `(,op (x ,{list 1 2}))} 'add}"),
+            "'(add (x (1 2)))"
+        );
+        assert_eq!(top_interp("`,{+ 1 2}"), "3");
+        assert_eq!(
+            top_interp("`(a `(b ,c))"),
+            "'(a (quasiquote (b (unquote c))))"
+        );
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        assert_eq!(top_interp_with("`(,{+ 1 2})", lazy), "'(3)");
+    }
+
+    #[test]
+    fn metacircular_evaluator() {
+        let src = "{define {lookup x env}
+                     {match env
+                       [{cons {list y v} rest} {if {equal? x y} v {lookup x rest}}]}}
+                   {define {ev e env}
+                     {match e
+                       [n #:when {symbol? n} {lookup n env}]
+                       [{list 'lambda {list x} body} {lambda (a) {ev body {cons {list x a} env}}}]
+                       [{list '+ l r} {+ {ev l env} {ev r env}}]
+                       [{list f a} {{ev f env} {ev a env}}]
+                       [n n]}}
+                   {ev `((lambda (x) (+ x ,{* 4 10})) 2) empty}";
+        assert_eq!(top_interp_program(src, Config::default()), vec!["42"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv symbol->string expected symbol")]
+    fn symbol_to_string_wrong_type() {
+        let _ = top_interp("{symbol->string \"a\"}");
+    }
+
+    #[test]
+    fn prelude_procedures() {
+        assert_eq!(top_interp("{identity \"x\"}"), "\"x\"");
+        assert_eq!(
+            top_interp("{{compose {lambda (x) {* x 2}} {lambda (x) {+ x 1}}} 5}"),
+            "12"
+        );
+        assert_eq!(top_interp("{{{curry -} 10} 3}"), "7");
+        assert_eq!(top_interp("{range 0 5}"), "'(0 1 2 3 4)");
+        assert_eq!(top_interp("{range 3 3}"), "'()");
+        assert_eq!(top_interp("{append {list 1 2} {list 3}}"), "'(1 2 3)");
+        assert_eq!(top_interp("{reverse {range 1 4}}"), "'(3 2 1)");
+        assert_eq!(top_interp("{list-ref {list 5 6 7} 2}"), "7");
+        assert_eq!(top_interp("{take {range 0 10} 3}"), "'(0 1 2)");
+        assert_eq!(top_interp("{drop {range 0 5} 3}"), "'(3 4)");
+        assert_eq!(top_interp("{take {range 0 2} 5}"), "'(0 1)");
+        assert_eq!(top_interp("{range 5 2}"), "'()");
+        assert_eq!(top_interp("{last {list 1 2 3}}"), "3");
+        assert_eq!(top_interp("{member? 2 {list 1 2 3}}"), "true");
+        assert_eq!(top_interp("{member? 9 {list 1 2 3}}"), "false");
+        assert_eq!(
+            top_interp("{andmap {lambda (x) {< x 3}} {list 1 2}}"),
+            "true"
+        );
+        assert_eq!(
+            top_interp("{ormap {lambda (x) {< x 0}} {list 1 2}}"),
+            "false"
+        );
+    }
+
+    #[test]
+    fn prelude_can_be_shadowed_or_disabled() {
+        assert_eq!(
+            top_interp_program("{define {range n} n} {range 4}", Config::default()),
+            vec!["4"]
+        );
+        let pure = Config {
+            prelude: false,
+            ..Config::default()
+        };
+        assert_eq!(base_env(pure), top_env());
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'range'")]
+    fn prelude_disabled() {
+        let pure = Config {
+            prelude: false,
+            ..Config::default()
+        };
+        let _ = top_interp_with("{range 0 2}", pure);
+    }
+
+    #[test]
+    fn prelude_is_evaluated_once() {
+        // both envs hold the very same definition Thunks, so the PRELUDE ran only once
+        assert_eq!(base_env(Config::default()), base_env(Config::default()));
+        let env = host_registry().env();
+        assert_eq!(
+            serialize(&interp(
+                &parse(&read(
+                    "{{{curry string-repeat} \"ab\"} {length {range 0 2}}}"
+                )),
+                &env
+            )),
+            "\"abab\""
+        );
+    }
+
+    #[test]
+    fn prelude_handles_long_lists() {
+        let program = "{define xs {range 0 5000}}
+                       {length xs}
+                       {length {take xs 4000}}
+                       {length {drop xs 10}}
+                       {list-ref xs 4999}
+                       {last xs}
+                       {member? 4999 xs}
+                       {andmap {lambda (x) {>= x 0}} xs}
+                       {ormap {lambda (x) {< x 0}} xs}";
+        assert_eq!(
+            top_interp_program(program, Config::default()),
+            vec![
+                "5000", "4000", "4990", "4999", "4999", "true", "true", "false"
+            ]
+        );
+    }
+
+    #[test]
+    fn prelude_andmap_stops_at_first_failure() {
+        assert_eq!(
+            top_interp("{andmap {lambda (x) {< {/ 1 x} 1}} {list 2 1 0}}"),
+            "false"
+        );
+        assert_eq!(
+            top_interp("{ormap {lambda (x) {> {/ 1 x} 0}} {list 1 0}}"),
+            "true"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "list-ref: index out of range")]
+    fn prelude_list_ref_out_of_range() {
+        let _ = top_interp("{list-ref {list 1} 1}");
+    }
+
+    #[test]
+    fn cond_when_unless() {
+        let grade = |n: i64| {
+            top_interp(&format!(
+                "{{cond [{{>= {} 90}} \"A\"] [{{>= {} 80}} \"B\"] [else \"C\"]}}",
+                n, n
+            ))
+        };
+        assert_eq!(grade(95), "\"A\"");
+        assert_eq!(grade(85), "\"B\"");
+        assert_eq!(grade(10), "\"C\"");
+        assert_eq!(top_interp("{cond [false 1]}"), "#<void>");
+        assert_eq!(top_interp("{cond}"), "#<void>");
+        assert_eq!(top_interp("{when {< 1 2} \"yes\"}"), "\"yes\"");
+        assert_eq!(top_interp("{when false {/ 1 0}}"), "#<void>");
+        assert_eq!(top_interp("{unless false 2}"), "2");
+        assert_eq!(top_interp("{equal? {unless true 2} {void}}"), "true");
+    }
+
+    #[test]
+    fn and_or_short_circuit() {
+        assert_eq!(top_interp("{and}"), "true");
+        assert_eq!(top_interp("{or}"), "false");
+        assert_eq!(top_interp("{and true {< 1 2}}"), "true");
+        assert_eq!(top_interp("{and true 5}"), "5");
+        assert_eq!(top_interp("{or false \"x\"}"), "\"x\"");
+        assert_eq!(top_interp("{and false {error \"boom\"}}"), "false");
+        assert_eq!(top_interp("{or true {/ 1 0}}"), "true");
+        assert_eq!(top_interp("{or false false {< 2 1}}"), "false");
+    }
+
+    #[test]
+    fn derived_forms_ignore_shadowed_booleans() {
+        assert_eq!(top_interp("{{lambda (false) {and {< 2 1} 3}} 7}"), "false");
+        assert_eq!(top_interp("{{lambda (true) {or {< 1 2} 3}} \"t\"}"), "true");
+        assert_eq!(top_interp("{{lambda (void) {when false 1}} 7}"), "#<void>");
+        assert_eq!(top_interp("{{lambda (true) {and}} 7}"), "true");
+        let e = parse(&read("{{lambda (false) {and {< 2 1} 3}} 7}"));
+        assert_eq!(serialize(&vm::vm_interp(&e)), "false");
+    }
+
+    #[test]
+    #[should_panic(expected = "cond expected boolean test, got 1")]
+    fn cond_non_bool_test() {
+        let _ = top_interp("{cond [1 2] [else 3]}");
+    }
+
+    #[test]
+    #[should_panic(expected = "and expected boolean test")]
+    fn and_non_bool_test() {
+        let _ = top_interp("{and 1 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "unless expected boolean test")]
+    fn unless_non_bool_test() {
+        let _ = top_interp("{unless \"no\" 2}");
+    }
+
+    #[test]
+    fn inexact_contagion() {
+        let sum = interp_prim(
+            &PrimV { op: PrimOp::Add },
+            vec![
+                Value::Exact(Rational::parse("1/2").unwrap()),
+                Value::Real(0.25),
+            ],
+        );
+        assert_eq!(sum, Value::Real(0.75));
+        assert_eq!(top_interp("{equal? 1 1.0}"), "false");
+    }
+
+    #[test]
+    #[should_panic(expected = "Divide by zero error")]
+    fn exact_div_zero_error() {
+        let _ = top_interp("{/ 1 0}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Primv substring expected 1 string and 2 exact integers")]
+    fn substr_inexact_index() {
+        let _ = top_interp("{substring \"hello\" 1.0 2}");
+    }
+}
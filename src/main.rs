@@ -1,4 +1,22 @@
+const USAGE: &str = "usage: sheq4r [--no-prelude] [--dynamic] [--lazy] [file.sheq]";
+
 fn main() {
-    // println!("Hello, world!");
-    sheq4r::run();
+    let mut config = sheq4r::Config::default();
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--no-prelude" => config.prelude = false,
+            "--dynamic" => config.scope = sheq4r::Scope::Dynamic,
+            "--lazy" => config.strategy = sheq4r::Strategy::Lazy,
+            _ if arg.starts_with("--") || path.is_some() => {
+                eprintln!("sheq4r: unexpected argument '{}'\n{}", arg, USAGE);
+                std::process::exit(2);
+            }
+            _ => path = Some(arg),
+        }
+    }
+    match path {
+        Some(path) => sheq4r::run_file_with(&path, config),
+        None => sheq4r::repl_with(config),
+    }
 }
@@ -0,0 +1,454 @@
+// Modules for SHEQ4: a program can require other SHEQ files and use the names they provide.
+//
+// Top ::= ... | {require String ...} | {provide id ...}
+//
+// A required path is resolved relative to the directory of the file that requires it. The first
+// require of a file under a Config evaluates it as a program of its own, in a copy of the base
+// globals the importer started from (base_env(), or an Interpreter's initial env and host
+// functions), and records the values of the names it provides; every later require of that file
+// under that Config reuses them, so a module runs once however many programs share it. Only
+// provided names are bound in the importer, before any of its own forms run, and a definition in
+// the importer shadows an import. A file that requires itself, directly or through other modules,
+// is a cyclic require and an error.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::parse::parse_program;
+use crate::{Config, Env, TopLevel, Value, interp_program};
+
+// Module - the bindings a module provides, and the Env it ran in, which is only held because it owns
+// the definitions its exports use, provided or not
+#[derive(Debug)]
+struct Module {
+    exports: Env,
+    _env: Env,
+}
+
+// Modules - the modules loaded so far and the ones being loaded
+#[derive(Debug, Clone, Default)]
+pub struct Modules {
+    // every module evaluated so far, by canonical path and the Config it ran under
+    loaded: HashMap<(PathBuf, Config), Rc<Module>>,
+    // modules whose evaluation has started but not finished, outermost first
+    loading: Vec<PathBuf>,
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        Modules::default()
+    }
+
+    // run_file - evaluates the program in the file at path in a copy of base, returning the Value
+    // of each expression
+    pub fn run_file(&mut self, path: &Path, base: &Env, config: Config) -> Vec<Value> {
+        let path = canonical(path);
+        self.loading.push(path.clone());
+        let mut env = base.clone();
+        let results = self.run_program(&read_module(&path), dir_of(&path), &mut env, base, config);
+        self.loading.pop();
+        results
+    }
+
+    // run_program - interp_program, after binding in env the exports of every module the forms
+    // require, with paths relative to dir. Modules evaluated for the first time start from base
+    pub fn run_program(
+        &mut self,
+        forms: &[TopLevel],
+        dir: &Path,
+        env: &mut Env,
+        base: &Env,
+        config: Config,
+    ) -> Vec<Value> {
+        // imported - each name bound so far and the module it came from
+        let mut imported: Vec<(String, PathBuf)> = Vec::new();
+        for form in forms {
+            let TopLevel::Require(paths) = form else {
+                continue;
+            };
+            for rel in paths {
+                let path = canonical(&dir.join(rel));
+                for export in self.load(&path, base, config).exports.iter() {
+                    match imported.iter().find(|(name, _)| *name == export.name) {
+                        Some((_, from)) if *from == path => {}
+                        Some((name, from)) => panic!(
+                            "SHEQ: {} is imported from both {} and {}",
+                            name,
+                            from.display(),
+                            path.display()
+                        ),
+                        None => {
+                            imported.push((export.name.clone(), path.clone()));
+                            env.push(export.clone());
+                        }
+                    }
+                }
+            }
+        }
+        interp_program(forms, env, config)
+    }
+
+    // load - the module at path, evaluating it in a copy of base on its first require under config
+    fn load(&mut self, path: &Path, base: &Env, config: Config) -> Rc<Module> {
+        let key = (path.to_path_buf(), config);
+        if let Some(module) = self.loaded.get(&key) {
+            return module.clone();
+        }
+        if let Some(start) = self.loading.iter().position(|p| p == path) {
+            let cycle: Vec<String> = self.loading[start..]
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect();
+            panic!(
+                "SHEQ: cyclic require: {} -> {}",
+                cycle.join(" -> "),
+                path.display()
+            );
+        }
+
+        self.loading.push(path.to_path_buf());
+        let forms = read_module(path);
+        let mut env = base.clone();
+        let start = env.len();
+        self.run_program(&forms, dir_of(path), &mut env, base, config);
+        let mut exports = Env::new();
+        for form in &forms {
+            let TopLevel::Provide(names) = form else {
+                continue;
+            };
+            for name in names {
+                match env[start..].iter().rev().find(|b| b.name == *name) {
+                    Some(b) => exports.push(b.clone()),
+                    None => panic!(
+                        "SHEQ: {} provides {}, which it does not define",
+                        path.display(),
+                        name
+                    ),
+                }
+            }
+        }
+        self.loading.pop();
+
+        let module = Rc::new(Module { exports, _env: env });
+        self.loaded.insert(key, module.clone());
+        module
+    }
+}
+
+// canonical - the absolute form of path, which names each file one way
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|e| panic!("SHEQ: could not read {}: {}", path.display(), e))
+}
+
+// dir_of - the directory of a canonical file path, which requires in that file are relative to
+fn dir_of(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new("/"))
+}
+
+// read_module - parses the program in the file at path
+fn read_module(path: &Path) -> Vec<TopLevel> {
+    let src = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("SHEQ: could not read {}: {}", path.display(), e));
+    parse_program(&src)
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arity, Rational, Strategy, base_env, serialize};
+    use std::ops::Deref;
+
+    // ModuleDir - a directory of module files that is removed when the test using it ends
+    struct ModuleDir(PathBuf);
+
+    impl Deref for ModuleDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for ModuleDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // module_dir - a fresh directory, named for the test using it, holding the given files
+    fn module_dir(test: &str, files: &[(&str, &str)]) -> ModuleDir {
+        let dir = std::env::temp_dir().join(format!("sheq-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, src) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, src).unwrap();
+        }
+        ModuleDir(dir)
+    }
+
+    // run - the serialized results of running main.sheq in dir
+    fn run(dir: &Path) -> Vec<String> {
+        let config = Config::default();
+        Modules::new()
+            .run_file(&dir.join("main.sheq"), &base_env(config), config)
+            .iter()
+            .map(serialize)
+            .collect()
+    }
+
+    const UTIL: &str = "{define {square x} {* x x}} {define {helper x} x} {provide square}";
+
+    #[test]
+    fn require_binds_provided_names() {
+        let dir = module_dir(
+            "provided",
+            &[
+                ("util.sheq", UTIL),
+                ("main.sheq", "{require \"util.sheq\"} {square 7}"),
+            ],
+        );
+        assert_eq!(run(&dir), vec!["49"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'helper'")]
+    fn unprovided_names_are_not_bound() {
+        let dir = module_dir(
+            "unprovided",
+            &[
+                ("util.sheq", UTIL),
+                ("main.sheq", "{require \"util.sheq\"} {helper 1}"),
+            ],
+        );
+        run(&dir);
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_importer() {
+        let dir = module_dir(
+            "relative",
+            &[
+                ("lib/b.sheq", "{define {g x} {* 10 x}} {provide g}"),
+                (
+                    "lib/a.sheq",
+                    "{require \"b.sheq\"} {define {f x} {+ {g x} 1}} {provide f}",
+                ),
+                ("main.sheq", "{require \"lib/a.sheq\"} {f 2}"),
+            ],
+        );
+        assert_eq!(run(&dir), vec!["21"]);
+    }
+
+    #[test]
+    fn modules_are_evaluated_once() {
+        // posn? only recognizes posns made by the same evaluation of define-struct
+        let dir = module_dir(
+            "once",
+            &[
+                (
+                    "posn.sheq",
+                    "{define-struct posn (x y)} {provide make-posn posn?}",
+                ),
+                (
+                    "a.sheq",
+                    "{require \"posn.sheq\"} {define origin {make-posn 0 0}} {provide origin}",
+                ),
+                (
+                    "b.sheq",
+                    "{require \"posn.sheq\"} {define {is-posn? p} {posn? p}} {provide is-posn?}",
+                ),
+                (
+                    "main.sheq",
+                    "{require \"a.sheq\" \"b.sheq\" \"posn.sheq\"} {is-posn? origin} {posn? origin}",
+                ),
+            ],
+        );
+        let mut modules = Modules::new();
+        let config = Config::default();
+        let results = modules.run_file(&dir.join("main.sheq"), &base_env(config), config);
+        assert_eq!(results, vec![Value::Boolean(true), Value::Boolean(true)]);
+        assert_eq!(modules.loaded.len(), 3);
+        assert!(modules.loading.is_empty());
+    }
+
+    #[test]
+    fn definitions_shadow_imports() {
+        let dir = module_dir(
+            "shadow",
+            &[
+                ("util.sheq", UTIL),
+                (
+                    "main.sheq",
+                    "{require \"util.sheq\"} {define {square x} {+ x x}} {square 7}",
+                ),
+            ],
+        );
+        assert_eq!(run(&dir), vec!["14"]);
+    }
+
+    #[test]
+    fn exports_use_unprovided_helpers() {
+        let dir = module_dir(
+            "helpers",
+            &[
+                (
+                    "util.sheq",
+                    "{define {quadruple x} {double {double x}}} {define {double x} {* x 2}} {provide quadruple}",
+                ),
+                ("main.sheq", "{require \"util.sheq\"} {quadruple 3}"),
+            ],
+        );
+        assert_eq!(run(&dir), vec!["12"]);
+        let mut sheq = crate::Interpreter::new();
+        sheq.eval_program(&format!(
+            "{{require \"{}\"}}",
+            dir.join("util.sheq").display()
+        ))
+        .unwrap();
+        assert_eq!(
+            sheq.eval_str("{quadruple 5}"),
+            Ok(Value::Exact(Rational::from(20)))
+        );
+    }
+
+    #[test]
+    fn interpreter_keeps_imports() {
+        let dir = module_dir("interpreter", &[("util.sheq", UTIL)]);
+        let mut sheq = crate::Interpreter::new();
+        sheq.eval_program(&format!(
+            "{{require \"{}\"}}",
+            dir.join("util.sheq").display()
+        ))
+        .unwrap();
+        assert_eq!(
+            sheq.eval_str("{square 5}"),
+            Ok(Value::Exact(Rational::from(25)))
+        );
+    }
+
+    #[test]
+    fn modules_see_host_functions() {
+        let dir = module_dir(
+            "host",
+            &[(
+                "util.sheq",
+                "{define {shout s} {string-append {exclaim s} \"!\"}} {provide shout}",
+            )],
+        );
+        let mut sheq = crate::Interpreter::new();
+        sheq.register("exclaim", Arity::Exact(1), |args| match args.as_slice() {
+            [Value::String(s)] => Value::String(s.to_uppercase()),
+            _ => panic!("SHEQ: exclaim expected a string, got {:?}", args),
+        });
+        sheq.eval_program("{define {exclaim s} s}").unwrap();
+        sheq.eval_program(&format!(
+            "{{require \"{}\"}}",
+            dir.join("util.sheq").display()
+        ))
+        .unwrap();
+        // the module sees the host's exclaim, not the one the importing program defined
+        assert_eq!(
+            sheq.eval_str("{shout \"hi\"}"),
+            Ok(Value::String("HI!".into()))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Divide by zero")]
+    fn modules_are_evaluated_once_per_config() {
+        let dir = module_dir(
+            "config",
+            &[
+                (
+                    "util.sheq",
+                    "{define bad {/ 1 0}} {define ok 1} {provide ok}",
+                ),
+                ("main.sheq", "{require \"util.sheq\"} ok"),
+            ],
+        );
+        let mut modules = Modules::new();
+        let lazy = Config {
+            strategy: Strategy::Lazy,
+            ..Config::default()
+        };
+        let results = modules.run_file(&dir.join("main.sheq"), &base_env(lazy), lazy);
+        assert_eq!(results, vec![Value::Exact(Rational::from(1))]);
+        // strictly, the module must run again, and its bad definition fails
+        let strict = Config::default();
+        modules.run_file(&dir.join("main.sheq"), &base_env(strict), strict);
+    }
+
+    #[test]
+    fn module_dirs_are_removed() {
+        let dir = module_dir("removed", &[("main.sheq", "1")]);
+        let path = dir.to_path_buf();
+        assert!(path.exists());
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[should_panic(expected = "cyclic require")]
+    fn cyclic_require() {
+        let dir = module_dir(
+            "cycle",
+            &[
+                ("a.sheq", "{require \"b.sheq\"} {define x 1} {provide x}"),
+                ("b.sheq", "{require \"a.sheq\"} {define y 2} {provide y}"),
+                ("main.sheq", "{require \"a.sheq\"} x"),
+            ],
+        );
+        run(&dir);
+    }
+
+    #[test]
+    #[should_panic(expected = "cyclic require")]
+    fn requiring_the_main_file_is_cyclic() {
+        let dir = module_dir(
+            "self",
+            &[(
+                "main.sheq",
+                "{require \"main.sheq\"} {define x 1} {provide x}",
+            )],
+        );
+        run(&dir);
+    }
+
+    #[test]
+    #[should_panic(expected = "provides nope, which it does not define")]
+    fn provide_undefined_name() {
+        let dir = module_dir(
+            "undefined",
+            &[
+                ("util.sheq", "{define x 1} {provide x nope}"),
+                ("main.sheq", "{require \"util.sheq\"} x"),
+            ],
+        );
+        run(&dir);
+    }
+
+    #[test]
+    #[should_panic(expected = "x is imported from both")]
+    fn conflicting_imports() {
+        let dir = module_dir(
+            "conflict",
+            &[
+                ("a.sheq", "{define x 1} {provide x}"),
+                ("b.sheq", "{define x 2} {provide x}"),
+                ("main.sheq", "{require \"a.sheq\" \"b.sheq\"} x"),
+            ],
+        );
+        run(&dir);
+    }
+
+    #[test]
+    #[should_panic(expected = "could not read")]
+    fn missing_module() {
+        let dir = module_dir("missing", &[("main.sheq", "{require \"gone.sheq\"}")]);
+        run(&dir);
+    }
+}
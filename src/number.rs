@@ -0,0 +1,769 @@
+// Exact numbers: arbitrary precision integers (BigInt) and rationals (Rational) built on them.
+// A Rational is always kept in lowest terms with a positive denominator, so structural equality
+// is numeric equality and an integer is a Rational with denominator 1.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// BigInt - sign and magnitude, the magnitude is base 2^32 digits, least significant first,
+// with no trailing zero digits (zero is the empty magnitude, never negative)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+// Rational - num/den in lowest terms, den > 0
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: BigInt,
+    den: BigInt,
+}
+
+// magnitude helpers, all on normalized little-endian digit vectors
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+// sub_mag - a - b, requires a >= b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, digit) in a.iter().enumerate() {
+        let mut diff = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if diff < 0 {
+            diff += 1 << 32;
+            1
+        } else {
+            0
+        };
+        out.push(diff as u32);
+    }
+    trim(out)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let cur = out[i + j] as u64 + *x as u64 * *y as u64 + carry;
+            out[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    trim(out)
+}
+
+// divrem_small - divides a magnitude by a single non-zero digit
+fn divrem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut out = vec![0u32; a.len()];
+    let mut rem = 0u64;
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        out[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+    (trim(out), rem as u32)
+}
+
+fn bit_len(a: &[u32]) -> usize {
+    match a.last() {
+        Some(top) => (a.len() - 1) * 32 + (32 - top.leading_zeros() as usize),
+        None => 0,
+    }
+}
+
+fn bit(a: &[u32], i: usize) -> bool {
+    (a[i / 32] >> (i % 32)) & 1 == 1
+}
+
+fn shl_mag(a: &[u32], bits: usize) -> Vec<u32> {
+    if a.is_empty() {
+        return Vec::new();
+    }
+    let (digits, bits) = (bits / 32, bits % 32);
+    let mut out = vec![0u32; digits];
+    let mut carry = 0u32;
+    for d in a {
+        if bits == 0 {
+            out.push(*d);
+        } else {
+            out.push((d << bits) | carry);
+            carry = d >> (32 - bits);
+        }
+    }
+    if carry > 0 {
+        out.push(carry);
+    }
+    out
+}
+
+// divrem_mag - quotient and remainder of magnitudes, b non-zero
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+    if b.len() == 1 {
+        let (q, r) = divrem_small(a, b[0]);
+        return (q, trim(vec![r]));
+    }
+    // binary long division, one quotient bit at a time
+    let mut quotient = vec![0u32; a.len()];
+    let mut rem: Vec<u32> = Vec::new();
+    for i in (0..bit_len(a)).rev() {
+        rem = shl_mag(&rem, 1);
+        if bit(a, i) {
+            if rem.is_empty() {
+                rem.push(1);
+            } else {
+                rem[0] |= 1;
+            }
+        }
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (trim(quotient), rem)
+}
+
+impl BigInt {
+    pub fn zero() -> Self {
+        BigInt {
+            neg: false,
+            mag: Vec::new(),
+        }
+    }
+
+    fn from_parts(neg: bool, mag: Vec<u32>) -> Self {
+        let mag = trim(mag);
+        BigInt {
+            neg: neg && !mag.is_empty(),
+            mag,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.neg
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::from_parts(false, self.mag.clone())
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.mag.first().is_none_or(|d| d % 2 == 0)
+    }
+
+    // divrem - quotient truncated toward zero, the remainder has the sign of self
+    pub fn divrem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        if other.is_zero() {
+            panic!("SHEQ: Divide by zero error");
+        }
+        let (q, r) = divrem_mag(&self.mag, &other.mag);
+        (
+            BigInt::from_parts(self.neg != other.neg, q),
+            BigInt::from_parts(self.neg, r),
+        )
+    }
+
+    pub fn pow(&self, mut exp: u64) -> BigInt {
+        let mut base = self.clone();
+        let mut acc = BigInt::from(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = &acc * &base;
+            }
+            base = &base * &base;
+            exp >>= 1;
+        }
+        acc
+    }
+
+    // isqrt - the floor of the square root of a non-negative integer, by Newton's method
+    pub fn isqrt(&self) -> BigInt {
+        if self.is_negative() {
+            panic!("SHEQ: isqrt of a negative integer");
+        }
+        if self.is_zero() {
+            return BigInt::zero();
+        }
+        let two = BigInt::from(2);
+        // 2^ceil(bits/2) is at least the root, so the iteration decreases to it
+        let mut x = BigInt::from(1).shl(self.bit_len().div_ceil(2));
+        loop {
+            let y = (&x + &self.divrem(&x).0).divrem(&two).0;
+            if y >= x {
+                return x;
+            }
+            x = y;
+        }
+    }
+
+    pub fn gcd(&self, other: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), other.abs());
+        while !b.is_zero() {
+            let (_, r) = a.divrem(&b);
+            a = b;
+            b = r;
+        }
+        a
+    }
+
+    pub fn bit_len(&self) -> usize {
+        bit_len(&self.mag)
+    }
+
+    fn shl(&self, bits: usize) -> BigInt {
+        BigInt::from_parts(self.neg, shl_mag(&self.mag, bits))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let m = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0u64, |acc, d| (acc << 32) | *d as u64);
+        if self.neg {
+            if m <= i64::MAX as u64 + 1 {
+                Some((m as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(m).ok()
+        }
+    }
+
+    // to_f64 - the nearest float
+    pub fn to_f64(&self) -> f64 {
+        let len = self.bit_len();
+        let to_u64 = |mag: &[u32]| {
+            mag.iter()
+                .rev()
+                .fold(0u64, |acc, d| (acc << 32) | *d as u64)
+        };
+        let m = if len <= 64 {
+            to_u64(&self.mag) as f64
+        } else {
+            // keep the top 64 bits, with the lowest one set if anything below was dropped
+            // so the conversion to f64 still rounds correctly
+            let shift = len - 64;
+            let (digits, bits) = (shift / 32, shift % 32);
+            let dropped = self.mag[..digits].iter().any(|d| *d != 0)
+                || (bits > 0 && self.mag[digits] & ((1 << bits) - 1) != 0);
+            let top: Vec<u32> = if bits == 0 {
+                self.mag[digits..].to_vec()
+            } else {
+                (digits..self.mag.len())
+                    .map(|i| {
+                        let hi = self.mag.get(i + 1).map_or(0, |d| d << (32 - bits));
+                        (self.mag[i] >> bits) | hi
+                    })
+                    .collect()
+            };
+            let top = to_u64(&trim(top)) | dropped as u64;
+            let mut f = top as f64;
+            let mut remaining = shift as i32;
+            while remaining > 0 {
+                let step = remaining.min(1000);
+                f *= 2f64.powi(step);
+                remaining -= step;
+            }
+            f
+        };
+        if self.neg { -m } else { m }
+    }
+
+    // parse - reads an optionally signed decimal integer
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let mut mag: Vec<u32> = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).ok()?;
+            let scale = 10u32.pow(chunk.len() as u32);
+            mag = add_mag(&mul_mag(&mag, &[scale]), &trim(vec![chunk.parse().ok()?]));
+        }
+        Some(BigInt::from_parts(neg, mag))
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        let m = n.unsigned_abs();
+        BigInt::from_parts(n < 0, vec![m as u32, (m >> 32) as u32])
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // peel off nine decimal digits at a time
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (q, r) = divrem_small(&mag, 1_000_000_000);
+            chunks.push(r);
+            mag = q;
+        }
+        let mut out = String::new();
+        if self.neg {
+            out.push('-');
+        }
+        out.push_str(&chunks.pop().unwrap_or(0).to_string());
+        for c in chunks.iter().rev() {
+            out.push_str(&format!("{:09}", c));
+        }
+        write!(f, "{}", out)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.neg, self.mag.clone())
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::from_parts(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_parts(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_parts(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &(-other)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.neg != other.neg, mul_mag(&self.mag, &other.mag))
+    }
+}
+
+impl Rational {
+    // new - num/den reduced to lowest terms, panics on a zero denominator
+    pub fn new(num: BigInt, den: BigInt) -> Self {
+        if den.is_zero() {
+            panic!("SHEQ: Divide by zero error");
+        }
+        let g = num.gcd(&den);
+        let (mut num, mut den) = (num.divrem(&g).0, den.divrem(&g).0);
+        if den.is_negative() {
+            num = -&num;
+            den = -&den;
+        }
+        Rational { num, den }
+    }
+
+    pub fn from_integer(n: BigInt) -> Self {
+        Rational {
+            num: n,
+            den: BigInt::from(1),
+        }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.num
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.den
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.den == BigInt::from(1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.num.is_negative()
+    }
+
+    pub fn abs(&self) -> Rational {
+        Rational {
+            num: self.num.abs(),
+            den: self.den.clone(),
+        }
+    }
+
+    // to_integer - the value as a BigInt, if it is an integer
+    pub fn to_integer(&self) -> Option<&BigInt> {
+        if self.is_integer() {
+            Some(&self.num)
+        } else {
+            None
+        }
+    }
+
+    pub fn floor(&self) -> Rational {
+        let (q, r) = self.num.divrem(&self.den);
+        let q = if r.is_negative() {
+            &q - &BigInt::from(1)
+        } else {
+            q
+        };
+        Rational::from_integer(q)
+    }
+
+    pub fn ceiling(&self) -> Rational {
+        -&(-self).floor()
+    }
+
+    pub fn truncate(&self) -> Rational {
+        Rational::from_integer(self.num.divrem(&self.den).0)
+    }
+
+    // round - nearest integer, ties go to the even one
+    pub fn round(&self) -> Rational {
+        let floor = self.floor();
+        let diff = self - &floor;
+        let half = Rational::new(BigInt::from(1), BigInt::from(2));
+        match diff.cmp(&half) {
+            Ordering::Less => floor,
+            Ordering::Greater => &floor + &Rational::from(1),
+            Ordering::Equal => {
+                if floor.num.is_even() {
+                    floor
+                } else {
+                    &floor + &Rational::from(1)
+                }
+            }
+        }
+    }
+
+    // pow - raises to an integer power, a negative power of zero panics like division by zero
+    pub fn pow(&self, exp: i64) -> Rational {
+        let e = exp.unsigned_abs();
+        let (num, den) = (self.num.pow(e), self.den.pow(e));
+        if exp < 0 {
+            Rational::new(den, num)
+        } else {
+            Rational { num, den }
+        }
+    }
+
+    // sqrt - the exact square root, if num and den are both perfect squares
+    pub fn sqrt(&self) -> Option<Rational> {
+        if self.is_negative() {
+            return None;
+        }
+        let (n, d) = (self.num.isqrt(), self.den.isqrt());
+        if &n * &n == self.num && &d * &d == self.den {
+            Some(Rational { num: n, den: d })
+        } else {
+            None
+        }
+    }
+
+    // to_f64 - nearest float, dividing with enough extra bits that the quotient keeps full precision
+    pub fn to_f64(&self) -> f64 {
+        if self.is_integer() {
+            return self.num.to_f64();
+        }
+        let shift = (64 + self.den.bit_len()).saturating_sub(self.num.bit_len());
+        let (q, rem) = self.num.abs().shl(shift).divrem(&self.den);
+        // a nonzero remainder sets the lowest bit, far below the rounding position, so a quotient
+        // that looks like an exact tie still rounds up when the true value is above it
+        let q = if !rem.is_zero() && q.is_even() {
+            &q + &BigInt::from(1)
+        } else {
+            q
+        };
+        let mut f = q.to_f64();
+        // scale down in steps so intermediate powers of two stay representable
+        let mut remaining = shift as i32;
+        while remaining > 0 {
+            let step = remaining.min(1000);
+            f *= 2f64.powi(-step);
+            remaining -= step;
+        }
+        if self.num.is_negative() { -f } else { f }
+    }
+
+    // from_f64 - the exact value of a finite float
+    pub fn from_f64(f: f64) -> Option<Rational> {
+        if !f.is_finite() {
+            return None;
+        }
+        let bits = f.to_bits();
+        let neg = bits >> 63 == 1;
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        let frac = bits & ((1 << 52) - 1);
+        let (mantissa, exp) = if exp == 0 {
+            (frac, -1074)
+        } else {
+            (frac | (1 << 52), exp - 1075)
+        };
+        let m = BigInt::from(mantissa as i64);
+        let m = if neg { -&m } else { m };
+        Some(if exp >= 0 {
+            Rational::from_integer(m.shl(exp as usize))
+        } else {
+            Rational::new(m, BigInt::from(1).shl((-exp) as usize))
+        })
+    }
+
+    // parse - reads an integer or a fraction like -1/3
+    pub fn parse(s: &str) -> Option<Rational> {
+        match s.split_once('/') {
+            Some((n, d)) => {
+                let den = BigInt::parse(d)?;
+                if den.is_zero() || d.starts_with(['+', '-']) {
+                    return None;
+                }
+                Some(Rational::new(BigInt::parse(n)?, den))
+            }
+            None => Some(Rational::from_integer(BigInt::parse(s)?)),
+        }
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(n: i64) -> Self {
+        Rational::from_integer(BigInt::from(n))
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.num * &other.den).cmp(&(&other.num * &self.den))
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Neg for &Rational {
+    type Output = Rational;
+    fn neg(self) -> Rational {
+        Rational {
+            num: -&self.num,
+            den: self.den.clone(),
+        }
+    }
+}
+
+impl Add for &Rational {
+    type Output = Rational;
+    fn add(self, other: &Rational) -> Rational {
+        Rational::new(
+            &(&self.num * &other.den) + &(&other.num * &self.den),
+            &self.den * &other.den,
+        )
+    }
+}
+
+impl Sub for &Rational {
+    type Output = Rational;
+    fn sub(self, other: &Rational) -> Rational {
+        self + &(-other)
+    }
+}
+
+impl Mul for &Rational {
+    type Output = Rational;
+    fn mul(self, other: &Rational) -> Rational {
+        Rational::new(&self.num * &other.num, &self.den * &other.den)
+    }
+}
+
+// division by an exact zero panics with the same error as the / primitive
+impl Div for &Rational {
+    type Output = Rational;
+    fn div(self, other: &Rational) -> Rational {
+        Rational::new(&self.num * &other.den, &self.den * &other.num)
+    }
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse(s).unwrap()
+    }
+
+    fn rat(s: &str) -> Rational {
+        Rational::parse(s).unwrap()
+    }
+
+    #[test]
+    fn bigint_arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        let (q, r) = b.divrem(&a);
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            ("-8".into(), "-9000000000900000000090".into())
+        );
+        assert_eq!(
+            BigInt::from(2).pow(100).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(BigInt::from(2).pow(64).to_i64(), None);
+    }
+
+    #[test]
+    fn bigint_ordering() {
+        assert!(big("-5") < big("3"));
+        assert!(big("-5") < big("-3"));
+        assert!(big("18446744073709551616") > big("18446744073709551615"));
+    }
+
+    #[test]
+    fn rational_arithmetic() {
+        assert_eq!((&rat("1/3") + &rat("1/6")).to_string(), "1/2");
+        assert_eq!((&rat("1/3") * &rat("3")).to_string(), "1");
+        assert_eq!((&rat("1") / &rat("-3")).to_string(), "-1/3");
+        assert_eq!(Rational::new(BigInt::from(4), BigInt::from(-2)), rat("-2"));
+        assert!(Rational::parse("4/-2").is_none());
+        assert!(Rational::parse("1/0").is_none());
+        assert!(rat("1/3") < rat("1/2"));
+    }
+
+    #[test]
+    fn rational_rounding() {
+        assert_eq!(rat("-7/2").floor(), rat("-4"));
+        assert_eq!(rat("-7/2").ceiling(), rat("-3"));
+        assert_eq!(rat("-7/2").truncate(), rat("-3"));
+        assert_eq!(rat("5/2").round(), rat("2"));
+        assert_eq!(rat("7/2").round(), rat("4"));
+        assert_eq!(rat("8/3").round(), rat("3"));
+    }
+
+    #[test]
+    fn roots_and_powers() {
+        assert_eq!(big("0").isqrt(), big("0"));
+        assert_eq!(big("15").isqrt(), big("3"));
+        assert_eq!(big("16").isqrt(), big("4"));
+        assert_eq!(
+            big("1267650600228229401496703205376").isqrt(),
+            big("1125899906842624")
+        );
+        assert_eq!(rat("9/4").sqrt(), Some(rat("3/2")));
+        assert_eq!(rat("2").sqrt(), None);
+        assert_eq!(rat("-4").sqrt(), None);
+        assert_eq!(rat("2/3").pow(3), rat("8/27"));
+        assert_eq!(rat("-2").pow(-3), rat("-1/8"));
+        assert_eq!(rat("0").pow(0), rat("1"));
+    }
+
+    #[test]
+    fn rational_floats() {
+        assert_eq!(rat("1/3").to_f64(), 1.0 / 3.0);
+        assert_eq!(rat("-5/2").to_f64(), -2.5);
+        // 1 + 2^-53 is halfway between 1 and the next float, these are just above and below it
+        let above = rat("3802951800684688626702574682113/3802951800684688204490109616128");
+        let below = rat("3802951800684688626702574682111/3802951800684688204490109616128");
+        assert_eq!(above.to_f64(), 1.0 + f64::EPSILON);
+        assert_eq!(below.to_f64(), 1.0);
+        assert_eq!(Rational::from_f64(0.5), Some(rat("1/2")));
+        assert_eq!(Rational::from_f64(-3.0), Some(rat("-3")));
+        assert_eq!(Rational::from_f64(f64::NAN), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Divide by zero error")]
+    fn rational_divide_by_zero() {
+        let _ = &rat("1") / &rat("0");
+    }
+}
@@ -0,0 +1,1005 @@
+// Concrete syntax for SHEQ4: a reader from source text to Sexps, and a parser from Sexps to ExprC.
+//
+// Program ::= Top ...
+// Top  ::= {define id Expr} | {define {id id ...} Expr} | {define-struct id (id ...)} | Expr
+//        | {define-syntax id {syntax-rules (id ...) [Pattern Template] ...}}, see expand
+//        | {require String ...} | {provide id ...}, see module
+// Expr ::= Num | String | id | #t | #f | #<void>
+//        | {if Expr Expr Expr}
+//        | {cond [Expr Expr] ... [else Expr]} where the else clause is optional
+//        | {and Expr ...} | {or Expr ...} | {when Expr Expr} | {unless Expr Expr}
+//        | {lambda (id ...) Expr}
+//        | {let [id = Expr] ... in Expr end}
+//        | {match Expr [Pat Expr] ... } where a clause may also be [Pat #:when Expr Expr]
+//        | {quote Datum} | {quasiquote Datum} where the Datum may hold {unquote Expr}
+//        | {Expr Expr ...}
+// Pat  ::= _ | id | Num | String | true | false | empty | {quote Datum}
+//        | {list Pat ...} | {cons Pat Pat} | {id Pat ...}
+// 'd, `d and ,e are read as {quote d}, {quasiquote d} and {unquote e}
+
+use crate::expand::{Macros, at_use_site, expansion_of};
+use crate::{
+    AppC, BoolC, ExactC, ExpansionC, ExprC, IdC, IfC, IfForm, LamC, MatchC, MatchClause, NumC,
+    Pattern, QuasiC, QuoteC, Rational, StringC, Template, TopLevel, ValC, is_reserved, serialize,
+    serialize_real,
+};
+
+// Sexp - an s-expression, the result of reading concrete syntax
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sexp {
+    Num(f64),
+    // integer and fraction literals, 3 or 1/3, are exact
+    Exact(Rational),
+    Str(String),
+    Sym(String),
+    List(Vec<Sexp>),
+}
+
+// read - reads exactly one Sexp from a source string
+pub fn read(src: &str) -> Sexp {
+    let mut forms = read_all(src);
+    if forms.len() != 1 {
+        panic!("SHEQ: expected exactly one expression, got {}", forms.len());
+    }
+    forms.remove(0)
+}
+
+// read_all - reads every Sexp in a source string, in order
+pub fn read_all(src: &str) -> Vec<Sexp> {
+    let mut reader = Reader {
+        chars: src.chars().collect(),
+        pos: 0,
+    };
+    let mut forms = Vec::new();
+    loop {
+        reader.skip_whitespace();
+        if reader.peek().is_none() {
+            return forms;
+        }
+        forms.push(reader.read_sexp());
+    }
+}
+
+// is_balanced - true when every open bracket in src has been closed, used by the REPL for multi-line input
+pub fn is_balanced(src: &str) -> bool {
+    let mut depth: i64 = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut in_comment = false;
+    for c in src.chars() {
+        if in_comment {
+            in_comment = c != '\n';
+        } else if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                '"' => in_string = true,
+                ';' => in_comment = true,
+                _ => {}
+            }
+        }
+    }
+    depth <= 0 && !in_string
+}
+
+// Reader - cursor over the source characters
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else if c == ';' {
+                // comments run to the end of the line
+                while let Some(c) = self.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn read_sexp(&mut self) -> Sexp {
+        self.skip_whitespace();
+        match self.peek() {
+            None => panic!("SHEQ: unexpected end of input"),
+            Some(open @ ('(' | '[' | '{')) => {
+                self.pos += 1;
+                let close = match open {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        None => panic!("SHEQ: missing closing '{}'", close),
+                        Some(c) if c == close => {
+                            self.pos += 1;
+                            return Sexp::List(items);
+                        }
+                        Some(c @ (')' | ']' | '}')) => {
+                            panic!(
+                                "SHEQ: expected '{}' to close '{}', got '{}'",
+                                close, open, c
+                            )
+                        }
+                        Some(_) => items.push(self.read_sexp()),
+                    }
+                }
+            }
+            Some(c @ (')' | ']' | '}')) => panic!("SHEQ: unexpected '{}'", c),
+            Some(c @ ('\'' | '`' | ',')) => {
+                self.pos += 1;
+                let kw = match c {
+                    '\'' => "quote",
+                    '`' => "quasiquote",
+                    _ => "unquote",
+                };
+                Sexp::List(vec![Sexp::Sym(kw.into()), self.read_sexp()])
+            }
+            Some('"') => {
+                self.pos += 1;
+                let mut s = String::new();
+                loop {
+                    match self.peek() {
+                        None => panic!("SHEQ: unterminated string"),
+                        Some('"') => {
+                            self.pos += 1;
+                            return Sexp::Str(s);
+                        }
+                        Some('\\') => {
+                            self.pos += 1;
+                            match self.peek() {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some('\\') => s.push('\\'),
+                                Some('"') => s.push('"'),
+                                other => panic!("SHEQ: invalid string escape {:?}", other),
+                            }
+                            self.pos += 1;
+                        }
+                        Some(c) => {
+                            s.push(c);
+                            self.pos += 1;
+                        }
+                    }
+                }
+            }
+            Some(_) => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || "()[]{}\";'`,".contains(c) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                let token: String = self.chars[start..self.pos].iter().collect();
+                read_atom(&token)
+            }
+        }
+    }
+}
+
+// read_atom - a token is a number if it parses as one, otherwise a symbol
+fn read_atom(token: &str) -> Sexp {
+    read_number(token).unwrap_or_else(|| Sexp::Sym(token.to_string()))
+}
+
+// read_number - a numeric literal, exact when it has no decimal point or exponent, also used by string->number
+pub(crate) fn read_number(token: &str) -> Option<Sexp> {
+    if let Some(n) = Rational::parse(token) {
+        return Some(Sexp::Exact(n));
+    }
+    // the special floats as serialize prints them
+    match token {
+        "+inf.0" => return Some(Sexp::Num(f64::INFINITY)),
+        "-inf.0" => return Some(Sexp::Num(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Sexp::Num(f64::NAN)),
+        _ => {}
+    }
+    let looks_numeric = token
+        .trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    match token.parse::<f64>() {
+        Ok(n) if looks_numeric => Some(Sexp::Num(n)),
+        _ => None,
+    }
+}
+
+// parse_program - reads and parses every top-level form of a SHEQ program
+pub fn parse_program(src: &str) -> Vec<TopLevel> {
+    parse_program_with(src, &mut Macros::new())
+}
+
+// parse_program_with - parse_program where the program's macros are added to macros,
+// and the macros already there are expanded too
+pub fn parse_program_with(src: &str, macros: &mut Macros) -> Vec<TopLevel> {
+    read_all(src)
+        .iter()
+        .filter_map(|form| macros.expand_top(form))
+        .map(|form| parse_top(&form))
+        .collect()
+}
+
+// parse_top - parses one top-level form, a definition or an expression
+pub fn parse_top(s: &Sexp) -> TopLevel {
+    // a macro use at the top level, its definition's value or expression names the use
+    if let Some((site, expansion)) = expansion_of(s) {
+        let expansion_c = |e| {
+            ExprC::ExpansionC(ExpansionC {
+                site: site.to_string(),
+                e: Box::new(e),
+            })
+        };
+        return match at_use_site(site, || parse_top(expansion)) {
+            TopLevel::Define(name, e) => TopLevel::Define(name, expansion_c(e)),
+            TopLevel::Expr(e) => TopLevel::Expr(expansion_c(e)),
+            other => other,
+        };
+    }
+    match s {
+        Sexp::List(items) if matches!(items.first(), Some(Sexp::Sym(kw)) if kw == "define") => {
+            match &items[1..] {
+                [Sexp::Sym(name), rhs] => TopLevel::Define(parse_id(name), parse(rhs)),
+                [Sexp::List(header), body] => match header.as_slice() {
+                    [Sexp::Sym(name), params @ ..] => TopLevel::Define(
+                        parse_id(name),
+                        ExprC::LamC(LamC {
+                            args: parse_params(params, s),
+                            body: Box::new(parse(body)),
+                        }),
+                    ),
+                    _ => panic!("SHEQ: ill-formed define, got {}", unparse_sexp(s)),
+                },
+                _ => panic!("SHEQ: ill-formed define, got {}", unparse_sexp(s)),
+            }
+        }
+        Sexp::List(items) if matches!(items.first(), Some(Sexp::Sym(kw)) if kw == "define-struct") => {
+            match &items[1..] {
+                [Sexp::Sym(name), Sexp::List(fields)] => {
+                    TopLevel::DefineStruct(parse_id(name), parse_params(fields, s))
+                }
+                _ => panic!("SHEQ: ill-formed define-struct, got {}", unparse_sexp(s)),
+            }
+        }
+        Sexp::List(items) if matches!(items.first(), Some(Sexp::Sym(kw)) if kw == "require") => {
+            TopLevel::Require(
+                items[1..]
+                    .iter()
+                    .map(|item| match item {
+                        Sexp::Str(path) => path.clone(),
+                        _ => panic!(
+                            "SHEQ: require expected a path string, got {}",
+                            unparse_sexp(item)
+                        ),
+                    })
+                    .collect(),
+            )
+        }
+        Sexp::List(items) if matches!(items.first(), Some(Sexp::Sym(kw)) if kw == "provide") => {
+            let mut names: Vec<String> = Vec::new();
+            for item in &items[1..] {
+                match item {
+                    Sexp::Sym(name) if names.contains(name) => {
+                        panic!("SHEQ: duplicate provide of {} in {}", name, unparse_sexp(s))
+                    }
+                    Sexp::Sym(name) => names.push(parse_id(name)),
+                    _ => panic!("SHEQ: provide expected a name, got {}", unparse_sexp(item)),
+                }
+            }
+            TopLevel::Provide(names)
+        }
+        _ => TopLevel::Expr(parse(s)),
+    }
+}
+
+// parse - takes a Sexp and returns the ExprC it represents
+pub fn parse(s: &Sexp) -> ExprC {
+    if let Some((site, expansion)) = expansion_of(s) {
+        return ExprC::ExpansionC(ExpansionC {
+            site: site.to_string(),
+            e: Box::new(at_use_site(site, || parse(expansion))),
+        });
+    }
+    match s {
+        Sexp::Num(n) => ExprC::NumC(NumC { n: *n }),
+        Sexp::Exact(n) => ExprC::ExactC(ExactC { n: n.clone() }),
+        Sexp::Str(s) => ExprC::StringC(StringC { s: s.clone() }),
+        Sexp::Sym(name) if name == "#t" || name == "#f" => ExprC::BoolC(BoolC { b: name == "#t" }),
+        Sexp::Sym(name) if name == "#<void>" => ExprC::VoidC,
+        Sexp::Sym(name) => ExprC::IdC(IdC {
+            name: parse_id(name),
+        }),
+        Sexp::List(items) => match items.as_slice() {
+            [Sexp::Sym(kw), rest @ ..] if kw == "if" => match rest {
+                [v, iftrue, iffalse] => {
+                    if_form(IfForm::If, parse(v), parse(iftrue), parse(iffalse))
+                }
+                _ => panic!("SHEQ: ill-formed if, got {}", unparse_sexp(s)),
+            },
+            [Sexp::Sym(kw), clauses @ ..] if kw == "cond" => parse_cond(clauses, s),
+            [Sexp::Sym(kw), args @ ..] if kw == "and" => parse_and_or(IfForm::And, args),
+            [Sexp::Sym(kw), args @ ..] if kw == "or" => parse_and_or(IfForm::Or, args),
+            [Sexp::Sym(kw), rest @ ..] if kw == "when" || kw == "unless" => match rest {
+                [test, body] if kw == "when" => {
+                    if_form(IfForm::When, parse(test), parse(body), ExprC::VoidC)
+                }
+                [test, body] => if_form(IfForm::Unless, parse(test), ExprC::VoidC, parse(body)),
+                _ => panic!("SHEQ: ill-formed {}, got {}", kw, unparse_sexp(s)),
+            },
+            [Sexp::Sym(kw), rest @ ..] if kw == "lambda" => match rest {
+                [Sexp::List(params), body] => ExprC::LamC(LamC {
+                    args: parse_params(params, s),
+                    body: Box::new(parse(body)),
+                }),
+                _ => panic!("SHEQ: ill-formed lambda, got {}", unparse_sexp(s)),
+            },
+            [Sexp::Sym(kw), rest @ ..] if kw == "let" => parse_let(rest, s),
+            [Sexp::Sym(kw), v, clauses @ ..] if kw == "match" && !clauses.is_empty() => {
+                ExprC::MatchC(MatchC {
+                    v: Box::new(parse(v)),
+                    clauses: clauses.iter().map(parse_clause).collect(),
+                })
+            }
+            [Sexp::Sym(kw), ..] if kw == "match" => {
+                panic!("SHEQ: ill-formed match, got {}", unparse_sexp(s))
+            }
+            [Sexp::Sym(kw), datum] if kw == "quote" => ExprC::QuoteC(QuoteC {
+                datum: datum.clone(),
+            }),
+            [Sexp::Sym(kw), template] if kw == "quasiquote" => ExprC::QuasiC(QuasiC {
+                template: parse_template(template, 1),
+            }),
+            [Sexp::Sym(kw), ..] if kw == "quote" || kw == "quasiquote" => {
+                panic!("SHEQ: ill-formed {}, got {}", kw, unparse_sexp(s))
+            }
+            [Sexp::Sym(kw), ..] if kw == "unquote" => {
+                panic!(
+                    "SHEQ: unquote outside of quasiquote, got {}",
+                    unparse_sexp(s)
+                )
+            }
+            [f, args @ ..] => ExprC::AppC(AppC {
+                expr: Box::new(parse(f)),
+                args: args.iter().map(|a| Box::new(parse(a))).collect(),
+            }),
+            [] => panic!("SHEQ: empty application"),
+        },
+    }
+}
+
+// if_form - an IfC written as form
+fn if_form(form: IfForm, v: ExprC, iftrue: ExprC, iffalse: ExprC) -> ExprC {
+    ExprC::IfC(IfC {
+        v: Box::new(v),
+        iftrue: Box::new(iftrue),
+        iffalse: Box::new(iffalse),
+        form,
+    })
+}
+
+// parse_cond - {cond [test Expr] ... [else Expr]} desugars to nested ifs,
+// when every test fails and there is no else clause the result is void
+fn parse_cond(clauses: &[Sexp], whole: &Sexp) -> ExprC {
+    let mut result = ExprC::VoidC;
+    for (i, clause) in clauses.iter().enumerate().rev() {
+        result = match clause {
+            Sexp::List(parts) => match parts.as_slice() {
+                [Sexp::Sym(kw), body] if kw == "else" => {
+                    if i != clauses.len() - 1 {
+                        panic!(
+                            "SHEQ: ill-formed cond, else must be the last clause, got {}",
+                            unparse_sexp(whole)
+                        );
+                    }
+                    parse(body)
+                }
+                [test, body] => if_form(IfForm::Cond, parse(test), parse(body), result),
+                _ => panic!("SHEQ: ill-formed cond clause, got {}", unparse_sexp(clause)),
+            },
+            _ => panic!("SHEQ: ill-formed cond clause, got {}", unparse_sexp(clause)),
+        };
+    }
+    result
+}
+
+// parse_and_or - {and Expr ...} and {or Expr ...} desugar to ifs that stop at the first false
+// or true test, the last Expr gives the result as is
+fn parse_and_or(form: IfForm, args: &[Sexp]) -> ExprC {
+    match args {
+        [] => ExprC::BoolC(BoolC {
+            b: form == IfForm::And,
+        }),
+        [last] => parse(last),
+        [first, rest @ ..] => {
+            let rest = parse_and_or(form, rest);
+            if form == IfForm::And {
+                if_form(form, parse(first), rest, ExprC::BoolC(BoolC { b: false }))
+            } else {
+                if_form(form, parse(first), ExprC::BoolC(BoolC { b: true }), rest)
+            }
+        }
+    }
+}
+
+// parse_template - a quasiquote template, depth counts the enclosing quasiquotes
+// so only an unquote of the outermost one is evaluated
+fn parse_template(s: &Sexp, depth: usize) -> Template {
+    match s {
+        Sexp::List(items) => {
+            let depth = match items.as_slice() {
+                [Sexp::Sym(kw), e] if kw == "unquote" => {
+                    if depth == 1 {
+                        return Template::Unquote(Box::new(parse(e)));
+                    }
+                    depth - 1
+                }
+                [Sexp::Sym(kw), _] if kw == "quasiquote" => depth + 1,
+                _ => depth,
+            };
+            let items: Vec<Template> = items.iter().map(|i| parse_template(i, depth)).collect();
+            if items.iter().all(|i| matches!(i, Template::Datum(_))) {
+                Template::Datum(s.clone())
+            } else {
+                Template::List(items)
+            }
+        }
+        _ => Template::Datum(s.clone()),
+    }
+}
+
+// parse_clause - a match clause, [Pat Expr] or [Pat #:when Expr Expr]
+fn parse_clause(clause: &Sexp) -> MatchClause {
+    let (pat, guard, body) = match clause {
+        Sexp::List(parts) => match parts.as_slice() {
+            [pat, body] => (pat, None, body),
+            [pat, Sexp::Sym(kw), guard, body] if kw == "#:when" => (pat, Some(guard), body),
+            _ => panic!(
+                "SHEQ: ill-formed match clause, got {}",
+                unparse_sexp(clause)
+            ),
+        },
+        _ => panic!(
+            "SHEQ: ill-formed match clause, got {}",
+            unparse_sexp(clause)
+        ),
+    };
+    let pat = parse_pattern(pat);
+    let vars = pat.vars();
+    for (i, name) in vars.iter().enumerate() {
+        if vars[..i].contains(name) {
+            panic!(
+                "SHEQ: duplicate pattern variable {} in {}",
+                name,
+                unparse_sexp(clause)
+            );
+        }
+    }
+    MatchClause {
+        pat,
+        guard: guard.map(|g| Box::new(parse(g))),
+        body: Box::new(parse(body)),
+    }
+}
+
+// parse_pattern - true, false and empty are literals, any other symbol but _ binds an id
+fn parse_pattern(s: &Sexp) -> Pattern {
+    match s {
+        Sexp::Num(n) => Pattern::Num(*n),
+        Sexp::Exact(n) => Pattern::Exact(n.clone()),
+        Sexp::Str(s) => Pattern::Str(s.clone()),
+        Sexp::Sym(name) => match name.as_str() {
+            "_" => Pattern::Wildcard,
+            "true" => Pattern::Bool(true),
+            "false" => Pattern::Bool(false),
+            "empty" => Pattern::Null,
+            _ => Pattern::Id(parse_id(name)),
+        },
+        Sexp::List(items) => match items.as_slice() {
+            [Sexp::Sym(kw), datum] if kw == "quote" => Pattern::Quote(datum.clone()),
+            [Sexp::Sym(kw), elems @ ..] if kw == "list" => {
+                elems.iter().rev().fold(Pattern::Null, |rest, p| {
+                    Pattern::Cons(Box::new(parse_pattern(p)), Box::new(rest))
+                })
+            }
+            [Sexp::Sym(kw), first, rest] if kw == "cons" => Pattern::Cons(
+                Box::new(parse_pattern(first)),
+                Box::new(parse_pattern(rest)),
+            ),
+            [Sexp::Sym(kw), ..] if kw == "cons" => {
+                panic!("SHEQ: ill-formed pattern, got {}", unparse_sexp(s))
+            }
+            [Sexp::Sym(name), fields @ ..] => {
+                Pattern::Struct(parse_id(name), fields.iter().map(parse_pattern).collect())
+            }
+            _ => panic!("SHEQ: ill-formed pattern, got {}", unparse_sexp(s)),
+        },
+    }
+}
+
+// parse_id - an identifier must not be a reserved word
+fn parse_id(name: &str) -> String {
+    if is_reserved(name) {
+        panic!("SHEQ: id name is a reserved word, got {}", name);
+    }
+    name.to_string()
+}
+
+// parse_params - lambda parameters must be distinct, non-reserved symbols
+fn parse_params(params: &[Sexp], whole: &Sexp) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for p in params {
+        match p {
+            Sexp::Sym(name) => {
+                let name = parse_id(name);
+                if names.contains(&name) {
+                    panic!(
+                        "SHEQ: duplicate parameter {} in {}",
+                        name,
+                        unparse_sexp(whole)
+                    );
+                }
+                names.push(name);
+            }
+            other => panic!("SHEQ: expected parameter name, got {}", unparse_sexp(other)),
+        }
+    }
+    names
+}
+
+// parse_let - {let [id = Expr] ... in body end} desugars to an application of a lambda
+fn parse_let(rest: &[Sexp], whole: &Sexp) -> ExprC {
+    match rest {
+        [clauses @ .., Sexp::Sym(kw_in), body, Sexp::Sym(kw_end)]
+            if kw_in == "in" && kw_end == "end" =>
+        {
+            let mut names = Vec::new();
+            let mut vals = Vec::new();
+            for clause in clauses {
+                match clause {
+                    Sexp::List(parts) => match parts.as_slice() {
+                        [name, Sexp::Sym(eq), val] if eq == "=" => {
+                            names.push(name.clone());
+                            vals.push(Box::new(parse(val)));
+                        }
+                        _ => panic!("SHEQ: ill-formed let clause, got {}", unparse_sexp(clause)),
+                    },
+                    other => panic!("SHEQ: ill-formed let clause, got {}", unparse_sexp(other)),
+                }
+            }
+            ExprC::AppC(AppC {
+                expr: Box::new(ExprC::LamC(LamC {
+                    args: parse_params(&names, whole),
+                    body: Box::new(parse(body)),
+                })),
+                args: vals,
+            })
+        }
+        _ => panic!("SHEQ: ill-formed let, got {}", unparse_sexp(whole)),
+    }
+}
+
+// unparse - prints an ExprC back in concrete syntax, used by the stepper
+pub fn unparse(e: &ExprC) -> String {
+    match e {
+        ExprC::NumC(NumC { n }) => serialize_real(*n),
+        ExprC::ExactC(ExactC { n }) => n.to_string(),
+        ExprC::StringC(StringC { s }) => format!("{:?}", s),
+        ExprC::BoolC(BoolC { b }) => if *b { "#t" } else { "#f" }.into(),
+        ExprC::IdC(IdC { name }) => name.clone(),
+        ExprC::VoidC => "#<void>".into(),
+        ExprC::ValC(ValC { v }) => serialize(v),
+        ExprC::ExpansionC(ExpansionC { e, .. }) => unparse(e),
+        ExprC::IfC(IfC {
+            v, iftrue, iffalse, ..
+        }) => format!(
+            "{{if {} {} {}}}",
+            unparse(v),
+            unparse(iftrue),
+            unparse(iffalse)
+        ),
+        ExprC::LamC(LamC { args, body }) => {
+            format!("{{lambda ({}) {}}}", args.join(" "), unparse(body))
+        }
+        ExprC::AppC(AppC { expr, args }) => {
+            let mut parts = vec![unparse(expr)];
+            parts.extend(args.iter().map(|a| unparse(a)));
+            format!("{{{}}}", parts.join(" "))
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => {
+            let mut parts = vec!["match".to_string(), unparse(v)];
+            for clause in clauses {
+                parts.push(match &clause.guard {
+                    Some(guard) => format!(
+                        "[{} #:when {} {}]",
+                        unparse_pattern(&clause.pat),
+                        unparse(guard),
+                        unparse(&clause.body)
+                    ),
+                    None => format!(
+                        "[{} {}]",
+                        unparse_pattern(&clause.pat),
+                        unparse(&clause.body)
+                    ),
+                });
+            }
+            format!("{{{}}}", parts.join(" "))
+        }
+        ExprC::QuoteC(QuoteC { datum }) => format!("'{}", unparse_datum(datum)),
+        ExprC::QuasiC(QuasiC { template }) => format!("`{}", unparse_template(template)),
+    }
+}
+
+// unparse_template - prints a quasiquote template, unquoted expressions after a comma
+fn unparse_template(t: &Template) -> String {
+    match t {
+        Template::Datum(datum) => unparse_datum(datum),
+        Template::Unquote(e) => format!(",{}", unparse(e)),
+        Template::List(items) => format!(
+            "({})",
+            items
+                .iter()
+                .map(unparse_template)
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+// unparse_datum - prints a quoted datum, with lists in parentheses as serialize prints them
+fn unparse_datum(s: &Sexp) -> String {
+    match s {
+        Sexp::List(items) => format!(
+            "({})",
+            items
+                .iter()
+                .map(unparse_datum)
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        other => unparse_sexp(other),
+    }
+}
+
+// unparse_pattern - prints a Pattern, Cons chains ending in Null as list patterns
+fn unparse_pattern(p: &Pattern) -> String {
+    match p {
+        Pattern::Wildcard => "_".into(),
+        Pattern::Id(name) => name.clone(),
+        Pattern::Num(n) => serialize_real(*n),
+        Pattern::Exact(n) => n.to_string(),
+        Pattern::Str(s) => format!("{:?}", s),
+        Pattern::Bool(b) => b.to_string(),
+        Pattern::Null => "empty".into(),
+        Pattern::Quote(datum) => format!("'{}", unparse_datum(datum)),
+        Pattern::Cons(first, rest) => {
+            let mut elems = vec![unparse_pattern(first)];
+            let mut tail = &**rest;
+            while let Pattern::Cons(first, rest) = tail {
+                elems.push(unparse_pattern(first));
+                tail = rest;
+            }
+            match tail {
+                Pattern::Null => format!("{{list {}}}", elems.join(" ")),
+                _ => {
+                    // an improper chain is printed as nested cons patterns
+                    let last = elems.pop().expect("SHEQ: cons pattern has a first");
+                    let inner = format!("{{cons {} {}}}", last, unparse_pattern(tail));
+                    elems
+                        .into_iter()
+                        .rev()
+                        .fold(inner, |acc, e| format!("{{cons {} {}}}", e, acc))
+                }
+            }
+        }
+        Pattern::Struct(name, fields) => {
+            let mut parts = vec![name.clone()];
+            parts.extend(fields.iter().map(unparse_pattern));
+            format!("{{{}}}", parts.join(" "))
+        }
+    }
+}
+
+// unparse_sexp - prints a Sexp back in concrete syntax, used in error messages
+pub fn unparse_sexp(s: &Sexp) -> String {
+    if let Some((_, expansion)) = expansion_of(s) {
+        return unparse_sexp(expansion);
+    }
+    match s {
+        Sexp::Num(n) => serialize_real(*n),
+        Sexp::Exact(n) => n.to_string(),
+        Sexp::Str(s) => format!("{:?}", s),
+        Sexp::Sym(name) => name.clone(),
+        Sexp::List(items) => format!(
+            "{{{}}}",
+            items.iter().map(unparse_sexp).collect::<Vec<_>>().join(" ")
+        ),
+    }
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_works() {
+        assert_eq!(read("12"), Sexp::Exact(Rational::from(12)));
+        assert_eq!(read("-2/4"), Sexp::Exact(Rational::parse("-1/2").unwrap()));
+        assert_eq!(read("-1.5"), Sexp::Num(-1.5));
+        assert_eq!(read("-"), Sexp::Sym("-".into()));
+        assert_eq!(read("\"a\\\"b\""), Sexp::Str("a\"b".into()));
+        assert_eq!(
+            read("{+ [x] ; comment\n 1}"),
+            Sexp::List(vec![
+                Sexp::Sym("+".into()),
+                Sexp::List(vec![Sexp::Sym("x".into())]),
+                Sexp::Exact(Rational::from(1)),
+            ])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "expected '}' to close '{'")]
+    fn read_mismatched_brackets() {
+        let _ = read("{+ 1 2)");
+    }
+
+    #[test]
+    fn parse_let_desugars() {
+        let e = parse(&read("{let [x = 1] in x end}"));
+        assert_eq!(
+            e,
+            ExprC::AppC(AppC {
+                expr: Box::new(ExprC::LamC(LamC {
+                    args: vec!["x".into()],
+                    body: Box::new(ExprC::IdC(IdC { name: "x".into() })),
+                })),
+                args: vec![Box::new(ExprC::ExactC(ExactC {
+                    n: Rational::from(1)
+                }))],
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate parameter x")]
+    fn parse_duplicate_params() {
+        let _ = parse(&read("{lambda (x x) x}"));
+    }
+
+    #[test]
+    #[should_panic(expected = "id name is a reserved word")]
+    fn parse_reserved_param() {
+        let _ = parse(&read("{lambda (in) 1}"));
+    }
+
+    #[test]
+    fn unparse_round_trips() {
+        let src = "{{lambda (x y) {if {<= x y} \"le\" y}} 1 2}";
+        assert_eq!(unparse(&parse(&read(src))), src);
+    }
+
+    #[test]
+    fn parse_define_struct() {
+        assert_eq!(
+            parse_program("{define-struct posn (x y)}"),
+            vec![TopLevel::DefineStruct(
+                "posn".into(),
+                vec!["x".into(), "y".into()]
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_require_provide() {
+        assert_eq!(
+            parse_program("{require \"util.sheq\" \"lib/list.sheq\"} {provide square cube}"),
+            vec![
+                TopLevel::Require(vec!["util.sheq".into(), "lib/list.sheq".into()]),
+                TopLevel::Provide(vec!["square".into(), "cube".into()]),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "require expected a path string, got util")]
+    fn parse_require_symbol() {
+        let _ = parse_program("{require util}");
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate provide of f")]
+    fn parse_duplicate_provide() {
+        let _ = parse_program("{provide f g f}");
+    }
+
+    #[test]
+    #[should_panic(expected = "ill-formed define-struct")]
+    fn parse_define_struct_without_fields() {
+        let _ = parse_program("{define-struct posn x}");
+    }
+
+    #[test]
+    fn parse_match() {
+        let src = "{match l [empty 0] [{cons x {list y z}} #:when {< x y} x] [{posn _ \"a\"} 1.5]}";
+        let e = parse(&read(src));
+        match &e {
+            ExprC::MatchC(MatchC { clauses, .. }) => {
+                assert_eq!(clauses[0].pat, Pattern::Null);
+                assert_eq!(clauses[1].pat.vars(), vec!["x", "y", "z"]);
+                assert!(clauses[1].guard.is_some());
+            }
+            other => panic!("SHEQ: expected a match, got {:?}", other),
+        }
+        // cons onto a list pattern unparses as one list pattern
+        let printed = unparse(&e);
+        assert_eq!(
+            printed,
+            "{match l [empty 0] [{list x y z} #:when {< x y} x] [{posn _ \"a\"} 1.5]}"
+        );
+        assert_eq!(parse(&read(&printed)), e);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate pattern variable x")]
+    fn parse_match_duplicate_var() {
+        let _ = parse(&read("{match l [{list x x} x]}"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ill-formed match clause")]
+    fn parse_match_bad_clause() {
+        let _ = parse(&read("{match l [x when y x]}"));
+    }
+
+    #[test]
+    fn read_quote_prefixes() {
+        let sym = |s: &str| Sexp::Sym(s.into());
+        assert_eq!(
+            read("'(a ,b)"),
+            Sexp::List(vec![
+                sym("quote"),
+                Sexp::List(vec![sym("a"), Sexp::List(vec![sym("unquote"), sym("b")])])
+            ])
+        );
+        assert_eq!(read("`x"), Sexp::List(vec![sym("quasiquote"), sym("x")]));
+        assert_eq!(read_all("a'b").len(), 2);
+    }
+
+    #[test]
+    fn parse_quote_and_quasiquote() {
+        assert_eq!(
+            parse(&read("'x")),
+            ExprC::QuoteC(QuoteC {
+                datum: Sexp::Sym("x".into())
+            })
+        );
+        // a template without unquotes is only a datum
+        assert_eq!(
+            parse(&read("`(a b)")),
+            ExprC::QuasiC(QuasiC {
+                template: Template::Datum(read("(a b)"))
+            })
+        );
+        for src in [
+            "'(a \"b\" 1 (c))",
+            "`(a ,x (b ,{f y}))",
+            "`(a (quasiquote (b (unquote (c ,x)))))",
+            "{match e ['x 1] [{list 'add l r} 2]}",
+        ] {
+            let e = parse(&read(src));
+            assert_eq!(unparse(&e), src);
+            assert_eq!(parse(&read(&unparse(&e))), e);
+        }
+        // only the unquote of the outermost quasiquote is evaluated
+        match parse(&read("`(a `(b ,(c ,x)))")) {
+            ExprC::QuasiC(QuasiC { template }) => {
+                assert_eq!(template.unquoted(), vec![&parse(&read("x"))]);
+            }
+            other => panic!("SHEQ: expected a quasiquote, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "unquote outside of quasiquote")]
+    fn parse_unquote_outside_quasiquote() {
+        let _ = parse(&read(",x"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ill-formed quote")]
+    fn parse_quote_arity() {
+        let _ = parse(&read("{quote a b}"));
+    }
+
+    #[test]
+    fn parse_derived_forms() {
+        let e = parse(&read("{cond [a 1] [else 2]}"));
+        assert_eq!(unparse(&e), "{if a 1 2}");
+        assert!(matches!(
+            e,
+            ExprC::IfC(IfC {
+                form: IfForm::Cond,
+                ..
+            })
+        ));
+        assert_eq!(
+            unparse(&parse(&read("{and a b c}"))),
+            "{if a {if b c #f} #f}"
+        );
+        assert_eq!(unparse(&parse(&read("{or a b}"))), "{if a #t b}");
+        assert_eq!(unparse(&parse(&read("{when a b}"))), "{if a b #<void>}");
+        assert_eq!(unparse(&parse(&read("{unless a b}"))), "{if a #<void> b}");
+    }
+
+    #[test]
+    fn literals_round_trip() {
+        for src in ["{and a b}", "{or a b}", "{when a b}", "{cond [a 1]}"] {
+            let printed = unparse(&parse(&read(src)));
+            assert_eq!(unparse(&parse(&read(&printed))), printed);
+        }
+        assert_eq!(parse(&read("#t")), ExprC::BoolC(BoolC { b: true }));
+        assert_eq!(parse(&read("#f")), ExprC::BoolC(BoolC { b: false }));
+        assert_eq!(parse(&read("#<void>")), ExprC::VoidC);
+    }
+
+    #[test]
+    #[should_panic(expected = "else must be the last clause")]
+    fn parse_cond_else_not_last() {
+        let _ = parse(&read("{cond [else 1] [a 2]}"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ill-formed when")]
+    fn parse_when_arity() {
+        let _ = parse(&read("{when a}"));
+    }
+
+    #[test]
+    fn parse_define_forms() {
+        assert_eq!(
+            parse_program("{define x 1} {define {f y} y} {f x}"),
+            vec![
+                TopLevel::Define("x".into(), parse(&read("1"))),
+                TopLevel::Define("f".into(), parse(&read("{lambda (y) y}"))),
+                TopLevel::Expr(parse(&read("{f x}"))),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "id name is a reserved word, got define")]
+    fn parse_nested_define() {
+        let _ = parse(&read("{+ 1 {define x 2}}"));
+    }
+
+    #[test]
+    fn is_balanced_works() {
+        assert!(is_balanced("{+ 1 2}"));
+        assert!(!is_balanced("{+ 1"));
+        assert!(is_balanced("{f \")\"}"));
+    }
+}
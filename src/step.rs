@@ -0,0 +1,235 @@
+// Small-step reduction for ExprC, in the style of the DrRacket stepper.
+// Each step performs exactly one beta reduction, if selection or primitive application,
+// leftmost-innermost, on top of the substitution evaluator. Free ids refer to the given globals.
+
+use crate::expand::at_use_site;
+use crate::subst::{apply, from_value, select_clause, to_value};
+use crate::{
+    AppC, Env, ExpansionC, ExprC, IdC, IfC, MatchC, QuasiC, Template, Value, build_template,
+    get_binding_val, is_reserved, serialize,
+};
+
+// step - performs one reduction on e, returning None when e is already a value
+pub fn step(e: &ExprC, globals: &Env) -> Option<ExprC> {
+    match e {
+        ExprC::NumC(_)
+        | ExprC::ExactC(_)
+        | ExprC::StringC(_)
+        | ExprC::BoolC(_)
+        | ExprC::LamC(_)
+        | ExprC::QuoteC(_)
+        | ExprC::VoidC
+        | ExprC::ValC(_) => None,
+        ExprC::IdC(IdC { name }) => {
+            if is_reserved(name) {
+                panic!("SHEQ: id name is a reserved word, got {}", name);
+            }
+            // a free id must refer to globals, panics if unbound
+            let _ = get_binding_val(name, globals);
+            None
+        }
+        // the expansion of a macro use steps inside the use, so a later error still names it
+        ExprC::ExpansionC(ExpansionC { site, e }) => {
+            at_use_site(site, || step(e, globals)).map(|e| {
+                ExprC::ExpansionC(ExpansionC {
+                    site: site.clone(),
+                    e: Box::new(e),
+                })
+            })
+        }
+        ExprC::IfC(IfC {
+            v,
+            iftrue,
+            iffalse,
+            form,
+        }) => match step(v, globals) {
+            Some(v) => Some(ExprC::IfC(IfC {
+                v: Box::new(v),
+                iftrue: iftrue.clone(),
+                iffalse: iffalse.clone(),
+                form: *form,
+            })),
+            None => match to_value(v, globals) {
+                Value::Boolean(true) => Some((**iftrue).clone()),
+                Value::Boolean(false) => Some((**iffalse).clone()),
+                other => panic!(
+                    "SHEQ: {} expected boolean test, got {}",
+                    form.name(),
+                    serialize(&other)
+                ),
+            },
+        },
+        ExprC::AppC(AppC { expr, args }) => {
+            if let Some(f) = step(expr, globals) {
+                return Some(ExprC::AppC(AppC {
+                    expr: Box::new(f),
+                    args: args.clone(),
+                }));
+            }
+            for (i, a) in args.iter().enumerate() {
+                if let Some(a) = step(a, globals) {
+                    let mut args = args.clone();
+                    *args[i] = a;
+                    return Some(ExprC::AppC(AppC {
+                        expr: expr.clone(),
+                        args,
+                    }));
+                }
+            }
+            Some(apply(
+                expr,
+                args.iter().map(|a| (**a).clone()).collect(),
+                globals,
+            ))
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => match step(v, globals) {
+            Some(v) => Some(ExprC::MatchC(MatchC {
+                v: Box::new(v),
+                clauses: clauses.clone(),
+            })),
+            None => Some(select_clause(v, clauses, globals)),
+        },
+        // the unquoted expressions step left to right, then the whole template becomes its value
+        ExprC::QuasiC(QuasiC { template }) => match step_template(template, globals) {
+            Some(template) => Some(ExprC::QuasiC(QuasiC { template })),
+            None => Some(from_value(&build_template(template, &mut |e| {
+                to_value(e, globals)
+            }))),
+        },
+    }
+}
+
+// step_template - steps the leftmost unquoted expression of t that is not yet a value
+fn step_template(t: &Template, globals: &Env) -> Option<Template> {
+    match t {
+        Template::Datum(_) => None,
+        Template::Unquote(e) => step(e, globals).map(|e| Template::Unquote(Box::new(e))),
+        Template::List(items) => items.iter().enumerate().find_map(|(i, item)| {
+            let stepped = step_template(item, globals)?;
+            let mut items = items.clone();
+            items[i] = stepped;
+            Some(Template::List(items))
+        }),
+    }
+}
+
+// steps - every intermediate program from e to its value, starting with e itself
+pub fn steps(e: &ExprC, globals: &Env) -> Vec<ExprC> {
+    let mut programs = vec![e.clone()];
+    while let Some(next) = step(programs.last().expect("SHEQ: no program to step"), globals) {
+        programs.push(next);
+    }
+    programs
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse, read, unparse};
+    use crate::{Config, base_env, top_env};
+
+    fn trace(src: &str) -> Vec<String> {
+        steps(&parse(&read(src)), &base_env(Config::default()))
+            .iter()
+            .map(unparse)
+            .collect()
+    }
+
+    #[test]
+    fn step_beta_and_prim() {
+        assert_eq!(
+            trace("{{lambda (x) {+ x 1}} {* 2 3}}"),
+            vec![
+                "{{lambda (x) {+ x 1}} {* 2 3}}",
+                "{{lambda (x) {+ x 1}} 6}",
+                "{+ 6 1}",
+                "7",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_if_selection() {
+        assert_eq!(
+            trace("{if {<= 1 2} \"yes\" \"no\"}"),
+            vec![
+                "{if {<= 1 2} \"yes\" \"no\"}",
+                "{if #t \"yes\" \"no\"}",
+                "\"yes\""
+            ]
+        );
+    }
+
+    #[test]
+    fn step_match_clause_selection() {
+        assert_eq!(
+            trace("{match {+ 1 2} [0 \"zero\"] [n #:when {> n 5} \"big\"] [n {* n n}]}"),
+            vec![
+                "{match {+ 1 2} [0 \"zero\"] [n #:when {> n 5} \"big\"] [n {* n n}]}",
+                "{match 3 [0 \"zero\"] [n #:when {> n 5} \"big\"] [n {* n n}]}",
+                "{if {> 3 5} \"big\" {match 3 [n {* n n}]}}",
+                "{if #f \"big\" {match 3 [n {* n n}]}}",
+                "{match 3 [n {* n n}]}",
+                "{* 3 3}",
+                "9",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_quasiquote() {
+        assert_eq!(
+            trace("`(a ,{+ 1 2} ,{* 2 2})"),
+            vec![
+                "`(a ,{+ 1 2} ,{* 2 2})",
+                "`(a ,3 ,{* 2 2})",
+                "`(a ,3 ,4)",
+                "'(a 3 4)",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_value_is_done() {
+        let env = top_env();
+        assert_eq!(step(&parse(&read("{lambda (x) {+ x 1}}")), &env), None);
+        assert_eq!(step(&parse(&read("+")), &env), None);
+    }
+
+    #[test]
+    fn step_prelude_procedure() {
+        assert_eq!(
+            trace("{length {range 0 {+ 1 2}}}"),
+            vec![
+                "{length {range 0 {+ 1 2}}}",
+                "{length {range 0 3}}",
+                "{length '(0 1 2)}",
+                "3",
+            ]
+        );
+    }
+
+    #[test]
+    fn step_returns_closure() {
+        assert_eq!(
+            trace("{{compose identity identity} 1}"),
+            vec!["{{compose identity identity} 1}", "{#<procedure> 1}", "1",]
+        );
+        assert_eq!(
+            trace("{{curry +} {+ 1 1}}"),
+            vec![
+                "{{curry +} {+ 1 1}}",
+                "{#<procedure> {+ 1 1}}",
+                "{#<procedure> 2}",
+                "#<procedure>"
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "if expected boolean test, got 1")]
+    fn step_non_bool_test() {
+        let _ = steps(&parse(&read("{if 1 2 3}")), &top_env());
+    }
+}
@@ -0,0 +1,554 @@
+// Substitution-based reference evaluator for ExprC.
+// Instead of an Env, applying a lambda replaces its parameters in the body with the argument values,
+// renaming inner binders where needed so substitution never captures a free identifier.
+// Identifiers still free after substitution refer to the globals, usually base_env(). A procedure bound
+// there that is not a lambda of the program, like the prelude's, is applied in one step as a primitive is.
+
+use std::collections::HashSet;
+
+use crate::expand::at_use_site;
+use crate::parse::Sexp;
+use crate::{
+    AppC, BoolC, CloV, Config, Env, ExactC, ExpansionC, ExprC, IdC, IfC, IfForm, LamC, MatchC,
+    MatchClause, NumC, Pattern, QuasiC, QuoteC, StringC, Template, ValC, Value, apply_value,
+    base_env, build_template, datum_value, force, get_binding_val, interp, interp_prim,
+    is_reserved, match_pattern, serialize, struct_type_named,
+};
+
+// subst_interp - evaluates an ExprC by substitution with free ids bound in globals, returning a Value
+pub fn subst_interp(e: &ExprC, globals: &Env) -> Value {
+    to_value(&reduce(e, globals), globals)
+}
+
+// cross_check - runs interp and subst_interp on the same program in the default base_env,
+// panicking when their results differ
+pub fn cross_check(e: &ExprC) -> Value {
+    let globals = base_env(Config::default());
+    let env_val = interp(e, &globals);
+    let subst_val = subst_interp(e, &globals);
+    if serialize(&env_val) != serialize(&subst_val) {
+        panic!(
+            "SHEQ: evaluators disagree on {:?}, interp gave {}, substitution gave {}",
+            e,
+            serialize(&env_val),
+            serialize(&subst_val)
+        );
+    }
+    env_val
+}
+
+// reduce - reduces an ExprC to a value form: NumC, ExactC, StringC, BoolC, LamC, QuoteC, VoidC, ValC,
+// or an IdC bound in globals
+pub(crate) fn reduce(e: &ExprC, globals: &Env) -> ExprC {
+    match e {
+        ExprC::NumC(_)
+        | ExprC::ExactC(_)
+        | ExprC::StringC(_)
+        | ExprC::BoolC(_)
+        | ExprC::LamC(_)
+        | ExprC::QuoteC(_)
+        | ExprC::VoidC
+        | ExprC::ValC(_) => e.clone(),
+        ExprC::IdC(IdC { name }) => {
+            if is_reserved(name) {
+                panic!("SHEQ: id name is a reserved word, got {}", name);
+            }
+            // panics if unbound, a bound id would have been substituted away
+            let _ = get_binding_val(name, globals);
+            e.clone()
+        }
+        ExprC::ExpansionC(ExpansionC { site, e }) => at_use_site(site, || reduce(e, globals)),
+        ExprC::IfC(IfC {
+            v,
+            iftrue,
+            iffalse,
+            form,
+        }) => match to_value(&reduce(v, globals), globals) {
+            Value::Boolean(true) => reduce(iftrue, globals),
+            Value::Boolean(false) => reduce(iffalse, globals),
+            other => panic!(
+                "SHEQ: {} expected boolean test, got {}",
+                form.name(),
+                serialize(&other)
+            ),
+        },
+        ExprC::AppC(AppC { expr, args }) => {
+            let f = reduce(expr, globals);
+            let arg_vals: Vec<ExprC> = args.iter().map(|a| reduce(a, globals)).collect();
+            reduce(&apply(&f, arg_vals, globals), globals)
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => reduce(
+            &select_clause(&reduce(v, globals), clauses, globals),
+            globals,
+        ),
+        ExprC::QuasiC(QuasiC { template }) => from_value(&build_template(template, &mut |e| {
+            to_value(&reduce(e, globals), globals)
+        })),
+    }
+}
+
+// select_clause - the expression a match on the value form v continues with: the body of the first
+// matching clause with its pattern ids substituted, inside an if on its guard when it has one,
+// whose else branch matches v against the remaining clauses
+pub(crate) fn select_clause(v: &ExprC, clauses: &[MatchClause], globals: &Env) -> ExprC {
+    let val = to_value(v, globals);
+    for (i, clause) in clauses.iter().enumerate() {
+        let mut binds = Vec::new();
+        if !match_pattern(
+            &clause.pat,
+            &val,
+            &|name| struct_type_named(name, globals),
+            &mut binds,
+        ) {
+            continue;
+        }
+        let map: Vec<(String, ExprC)> = clause
+            .pat
+            .vars()
+            .into_iter()
+            .zip(binds.iter().map(from_value))
+            .collect();
+        let body = subst(&clause.body, &map);
+        return match &clause.guard {
+            Some(guard) => ExprC::IfC(IfC {
+                v: Box::new(subst(guard, &map)),
+                iftrue: Box::new(body),
+                iffalse: Box::new(ExprC::MatchC(MatchC {
+                    v: Box::new(v.clone()),
+                    clauses: clauses[i + 1..].to_vec(),
+                })),
+                form: IfForm::If,
+            }),
+            None => body,
+        };
+    }
+    panic!("SHEQ: no match clause matched {}", serialize(&val))
+}
+
+// apply - performs one application of a value form to reduced arguments, returning the resulting ExprC
+pub(crate) fn apply(f: &ExprC, arg_vals: Vec<ExprC>, globals: &Env) -> ExprC {
+    match f {
+        ExprC::LamC(LamC { args: params, body }) => {
+            if arg_vals.len() != params.len() {
+                panic!(
+                    "SHEQ: Incorrect number of arguments, got {}, expected {}",
+                    arg_vals.len(),
+                    params.len()
+                );
+            }
+            let map: Vec<(String, ExprC)> = params.iter().cloned().zip(arg_vals).collect();
+            subst(body, &map)
+        }
+        ExprC::ExpansionC(ExpansionC { e, .. }) => apply(e, arg_vals, globals),
+        _ => match to_value(f, globals) {
+            Value::PrimV(prim) => from_value(&interp_prim(
+                &prim,
+                arg_vals.iter().map(|a| to_value(a, globals)).collect(),
+                globals,
+                Config::default(),
+            )),
+            // a procedure bound in globals, its body is not part of the program being reduced
+            f @ (Value::CloV(_) | Value::HostV(_) | Value::StructOp(_)) => {
+                let args = arg_vals.iter().map(|a| to_value(a, globals)).collect();
+                from_value(&force(apply_value(f, args, globals, Config::default())))
+            }
+            other => panic!("SHEQ: attempted to apply non function value of {:?}", other),
+        },
+    }
+}
+
+// subst - simultaneously replaces the free occurrences of each name in map, avoiding capture
+pub(crate) fn subst(e: &ExprC, map: &[(String, ExprC)]) -> ExprC {
+    if map.is_empty() {
+        return e.clone();
+    }
+    match e {
+        ExprC::NumC(_)
+        | ExprC::ExactC(_)
+        | ExprC::StringC(_)
+        | ExprC::BoolC(_)
+        | ExprC::QuoteC(_)
+        | ExprC::VoidC
+        | ExprC::ValC(_) => e.clone(),
+        ExprC::QuasiC(QuasiC { template }) => ExprC::QuasiC(QuasiC {
+            template: subst_template(template, map),
+        }),
+        ExprC::IdC(IdC { name }) => match map.iter().find(|(n, _)| n == name) {
+            Some((_, replacement)) => replacement.clone(),
+            None => e.clone(),
+        },
+        ExprC::ExpansionC(ExpansionC { site, e }) => ExprC::ExpansionC(ExpansionC {
+            site: site.clone(),
+            e: Box::new(subst(e, map)),
+        }),
+        ExprC::IfC(IfC {
+            v,
+            iftrue,
+            iffalse,
+            form,
+        }) => ExprC::IfC(IfC {
+            v: Box::new(subst(v, map)),
+            iftrue: Box::new(subst(iftrue, map)),
+            iffalse: Box::new(subst(iffalse, map)),
+            form: *form,
+        }),
+        ExprC::AppC(AppC { expr, args }) => ExprC::AppC(AppC {
+            expr: Box::new(subst(expr, map)),
+            args: args.iter().map(|a| Box::new(subst(a, map))).collect(),
+        }),
+        ExprC::LamC(LamC { args: params, body }) => {
+            let (new_params, mut bodies) = subst_under(params, &[body], map);
+            ExprC::LamC(LamC {
+                args: new_params,
+                body: Box::new(bodies.remove(0)),
+            })
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => ExprC::MatchC(MatchC {
+            v: Box::new(subst(v, map)),
+            clauses: clauses
+                .iter()
+                .map(|clause| {
+                    let vars = clause.pat.vars();
+                    let mut under = vec![&*clause.body];
+                    under.extend(clause.guard.as_deref());
+                    let (new_vars, mut bodies) = subst_under(&vars, &under, map);
+                    MatchClause {
+                        pat: rename_pattern(&clause.pat, &vars, &new_vars),
+                        body: Box::new(bodies.remove(0)),
+                        guard: clause.guard.as_ref().map(|_| Box::new(bodies.remove(0))),
+                    }
+                })
+                .collect(),
+        }),
+    }
+}
+
+// subst_under - substitutes map into exprs that sit under the binders params, which shadow the
+// outer substitution. Any binder that would capture a free id of a replacement is renamed.
+// Returns the binders, renamed where needed, and the substituted exprs
+fn subst_under(
+    params: &[String],
+    exprs: &[&ExprC],
+    map: &[(String, ExprC)],
+) -> (Vec<String>, Vec<ExprC>) {
+    let mut inner: Vec<(String, ExprC)> = map
+        .iter()
+        .filter(|(n, _)| !params.contains(n))
+        .cloned()
+        .collect();
+    if inner.is_empty() {
+        return (
+            params.to_vec(),
+            exprs.iter().map(|e| (*e).clone()).collect(),
+        );
+    }
+
+    // rename any param that would capture a free id of a replacement
+    let mut avoid = HashSet::new();
+    for (_, replacement) in &inner {
+        free_vars(replacement, &mut avoid);
+    }
+    let mut body_fv = HashSet::new();
+    for e in exprs {
+        free_vars(e, &mut body_fv);
+    }
+
+    let mut new_params = Vec::new();
+    for p in params {
+        if avoid.contains(p) {
+            let fresh = fresh_name(p, &avoid, &body_fv, params);
+            avoid.insert(fresh.clone());
+            inner.push((
+                p.clone(),
+                ExprC::IdC(IdC {
+                    name: fresh.clone(),
+                }),
+            ));
+            new_params.push(fresh);
+        } else {
+            new_params.push(p.clone());
+        }
+    }
+    (new_params, exprs.iter().map(|e| subst(e, &inner)).collect())
+}
+
+// subst_template - substitutes map into the unquoted expressions of a quasiquote template
+fn subst_template(t: &Template, map: &[(String, ExprC)]) -> Template {
+    match t {
+        Template::Datum(_) => t.clone(),
+        Template::Unquote(e) => Template::Unquote(Box::new(subst(e, map))),
+        Template::List(items) => {
+            Template::List(items.iter().map(|i| subst_template(i, map)).collect())
+        }
+    }
+}
+
+// rename_pattern - renames the pattern ids in from to the matching names in to
+fn rename_pattern(p: &Pattern, from: &[String], to: &[String]) -> Pattern {
+    match p {
+        Pattern::Id(name) => match from.iter().position(|n| n == name) {
+            Some(i) => Pattern::Id(to[i].clone()),
+            None => p.clone(),
+        },
+        Pattern::Cons(first, rest) => Pattern::Cons(
+            Box::new(rename_pattern(first, from, to)),
+            Box::new(rename_pattern(rest, from, to)),
+        ),
+        Pattern::Struct(name, fields) => Pattern::Struct(
+            name.clone(),
+            fields.iter().map(|f| rename_pattern(f, from, to)).collect(),
+        ),
+        _ => p.clone(),
+    }
+}
+
+// free_vars - adds the free identifiers of e to acc
+pub(crate) fn free_vars(e: &ExprC, acc: &mut HashSet<String>) {
+    match e {
+        ExprC::NumC(_)
+        | ExprC::ExactC(_)
+        | ExprC::StringC(_)
+        | ExprC::BoolC(_)
+        | ExprC::QuoteC(_)
+        | ExprC::VoidC
+        | ExprC::ValC(_) => {}
+        ExprC::QuasiC(QuasiC { template }) => {
+            for e in template.unquoted() {
+                free_vars(e, acc);
+            }
+        }
+        ExprC::IdC(IdC { name }) => {
+            acc.insert(name.clone());
+        }
+        ExprC::ExpansionC(ExpansionC { e, .. }) => free_vars(e, acc),
+        ExprC::IfC(IfC {
+            v, iftrue, iffalse, ..
+        }) => {
+            free_vars(v, acc);
+            free_vars(iftrue, acc);
+            free_vars(iffalse, acc);
+        }
+        ExprC::AppC(AppC { expr, args }) => {
+            free_vars(expr, acc);
+            for a in args {
+                free_vars(a, acc);
+            }
+        }
+        ExprC::LamC(LamC { args, body }) => {
+            let mut inner = HashSet::new();
+            free_vars(body, &mut inner);
+            for name in inner {
+                if !args.contains(&name) {
+                    acc.insert(name);
+                }
+            }
+        }
+        ExprC::MatchC(MatchC { v, clauses }) => {
+            free_vars(v, acc);
+            for clause in clauses {
+                let vars = clause.pat.vars();
+                let mut inner = HashSet::new();
+                free_vars(&clause.body, &mut inner);
+                if let Some(guard) = &clause.guard {
+                    free_vars(guard, &mut inner);
+                }
+                for name in inner {
+                    if !vars.contains(&name) {
+                        acc.insert(name);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// fresh_name - a variant of name that is not used by any of the given sets
+fn fresh_name(
+    name: &str,
+    avoid: &HashSet<String>,
+    body_fv: &HashSet<String>,
+    params: &[String],
+) -> String {
+    (1..)
+        .map(|i| format!("{}{}", name, i))
+        .find(|n| !avoid.contains(n) && !body_fv.contains(n) && !params.contains(n))
+        .expect("SHEQ: ran out of fresh names")
+}
+
+// to_value - converts a value form into the Value interp would produce in globals
+pub(crate) fn to_value(e: &ExprC, globals: &Env) -> Value {
+    match e {
+        ExprC::NumC(NumC { n }) => Value::Real(*n),
+        ExprC::ExactC(ExactC { n }) => Value::Exact(n.clone()),
+        ExprC::StringC(StringC { s }) => Value::String(s.clone()),
+        ExprC::BoolC(BoolC { b }) => Value::Boolean(*b),
+        ExprC::IdC(IdC { name }) => force(get_binding_val(name, globals)),
+        ExprC::LamC(LamC { args, body }) => Value::CloV(CloV {
+            params: args.clone(),
+            body: body.clone(),
+            env: globals.clone(),
+        }),
+        ExprC::QuoteC(QuoteC { datum }) => datum_value(datum),
+        ExprC::VoidC => Value::Void,
+        ExprC::ValC(ValC { v }) => v.clone(),
+        ExprC::ExpansionC(ExpansionC { e, .. }) => to_value(e, globals),
+        other => panic!("SHEQ: expected a value, got {:?}", other),
+    }
+}
+
+// from_value - converts a Value back into a value form. Literals and quotable lists keep their syntax,
+// anything else, like a procedure or a list holding one, is carried as a ValC, never as an id
+// that the program could have rebound
+pub(crate) fn from_value(v: &Value) -> ExprC {
+    match v {
+        Value::Real(n) => ExprC::NumC(NumC { n: *n }),
+        Value::Exact(n) => ExprC::ExactC(ExactC { n: n.clone() }),
+        Value::String(s) => ExprC::StringC(StringC { s: s.clone() }),
+        Value::Boolean(b) => ExprC::BoolC(BoolC { b: *b }),
+        Value::Void => ExprC::VoidC,
+        Value::Thunk(_) => from_value(&force(v.clone())),
+        Value::Symbol(_) | Value::Null | Value::Pair(_) => match value_datum(v) {
+            Some(datum) => ExprC::QuoteC(QuoteC { datum }),
+            None => ExprC::ValC(ValC { v: v.clone() }),
+        },
+        other => ExprC::ValC(ValC { v: other.clone() }),
+    }
+}
+
+// value_datum - the datum a quote of which gives v, if there is one: booleans and procedures have none
+fn value_datum(v: &Value) -> Option<Sexp> {
+    match v {
+        Value::Real(n) => Some(Sexp::Num(*n)),
+        Value::Exact(n) => Some(Sexp::Exact(n.clone())),
+        Value::String(s) => Some(Sexp::Str(s.clone())),
+        Value::Symbol(name) => Some(Sexp::Sym(name.clone())),
+        Value::Null | Value::Pair(_) => v
+            .list_items()?
+            .iter()
+            .map(value_datum)
+            .collect::<Option<Vec<Sexp>>>()
+            .map(Sexp::List),
+        _ => None,
+    }
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse, read};
+
+    fn check(src: &str) -> String {
+        serialize(&cross_check(&parse(&read(src))))
+    }
+
+    #[test]
+    fn subst_interp_works() {
+        assert_eq!(check("{+ 1 2}"), "3");
+        assert_eq!(
+            check("{{lambda (x) {if {<= x 1} \"small\" \"big\"}} 5}"),
+            "\"big\""
+        );
+        assert_eq!(check("{{{lambda (x) {lambda (y) {- x y}}} 10} 4}"), "6");
+        assert_eq!(check("{lambda (x) x}"), "#<procedure>");
+        assert_eq!(
+            check(
+                "{let [x = 1] in {let [f = {lambda (y) {+ x y}}] in {let [x = 100] in {f 1} end} end} end}"
+            ),
+            "2"
+        );
+    }
+
+    #[test]
+    fn subst_avoids_capture() {
+        // substituting the top-level + under a binder named + must rename the binder
+        assert_eq!(check("{{lambda (f) {{lambda (+) {f 1 2}} *}} +}"), "3");
+
+        let body = parse(&read("{lambda (y) {x y}}"));
+        let result = subst(&body, &[("x".into(), ExprC::IdC(IdC { name: "y".into() }))]);
+        assert_eq!(result, parse(&read("{lambda (y1) {y y1}}")));
+    }
+
+    #[test]
+    fn subst_respects_shadowing() {
+        let body = parse(&read("{lambda (x) x}"));
+        let result = subst(&body, &[("x".into(), ExprC::NumC(NumC { n: 1.0 }))]);
+        assert_eq!(result, body);
+    }
+
+    #[test]
+    fn subst_quasiquote() {
+        assert_eq!(check("'(a \"b\" 1)"), "'(a \"b\" 1)");
+        assert_eq!(
+            check("{{lambda (x) `(x ,x ,{+ x 1} (,'y))} 2}"),
+            "'(x 2 3 (y))"
+        );
+        assert_eq!(check("{symbol->string {first '(ab c)}}"), "\"ab\"");
+    }
+
+    #[test]
+    fn subst_match() {
+        assert_eq!(
+            check("{match 3 [0 \"zero\"] [n #:when {< n 0} \"neg\"] [n {* n n}]}"),
+            "9"
+        );
+        assert_eq!(check("{match \"a\" [\"b\" 1] [_ 2]}"), "2");
+        // the clause's x shadows the substituted x, and its y must not capture the outer y
+        let body = parse(&read("{match 1 [y {+ x y}] [x x]}"));
+        let result = subst(&body, &[("x".into(), ExprC::IdC(IdC { name: "y".into() }))]);
+        assert_eq!(result, parse(&read("{match 1 [y1 {+ y y1}] [x x]}")));
+    }
+
+    #[test]
+    fn subst_sees_prelude() {
+        assert_eq!(check("{reverse {range 0 3}}"), "'(2 1 0)");
+        assert_eq!(check("{{lambda (xs) {last xs}} {list 1 2}}"), "2");
+    }
+
+    #[test]
+    fn subst_higher_order_prelude() {
+        assert_eq!(check("{{compose identity identity} 1}"), "1");
+        assert_eq!(check("{{curry +} 1}"), "#<procedure>");
+        assert_eq!(check("{{{curry +} 1} 2}"), "3");
+        assert_eq!(check("{{compose {curry *} {curry +}} 1}"), "#<procedure>");
+        assert_eq!(
+            check("{map {curry +} '(1 2)}"),
+            "'(#<procedure> #<procedure>)"
+        );
+        assert_eq!(
+            check("{andmap {compose not {{curry equal?} 2}} '(1 3)}"),
+            "true"
+        );
+    }
+
+    #[test]
+    fn subst_keeps_booleans_and_structs() {
+        let globals = base_env(Config::default());
+        assert_eq!(
+            reduce(&parse(&read("{< 1 2}")), &globals),
+            ExprC::BoolC(BoolC { b: true })
+        );
+        // a rebound true or false does not change the value of a comparison
+        assert_eq!(
+            check("{{lambda (true false) {if {< 1 2} {< 2 1} true}} 1 2}"),
+            "false"
+        );
+        assert_eq!(
+            check("{{lambda (f) {{f {< 1 2}} {< 2 1}}} {lambda (true) {lambda (false) true}}}"),
+            "true"
+        );
+        assert_eq!(check("{hash-ref {{lambda (h) h} {hash 'a 1}} 'a}"), "1");
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'y'")]
+    fn subst_unbound() {
+        let _ = subst_interp(&parse(&read("{{lambda (x) y} 1}")), &crate::top_env());
+    }
+
+    #[test]
+    #[should_panic(expected = "if expected boolean test, got \"no\"")]
+    fn subst_non_bool_test() {
+        let _ = subst_interp(&parse(&read("{if \"no\" 1 2}")), &crate::top_env());
+    }
+}
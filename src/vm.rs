@@ -0,0 +1,752 @@
+// Bytecode compiler and stack VM for ExprC.
+// compile resolves every identifier ahead of time (locals to a depth and index, top env ids to a slot),
+// so the VM never searches an Env by name. Calls in tail position reuse the caller's frame.
+// The VM always evaluates strictly with static scope. A closure passed to a higher-order primitive
+// like map is called back in the VM. A CloV, like a prelude procedure or a closure taken out of
+// a list, is compiled against a copy of its Env when it is called, and run in the VM as well.
+
+use std::rc::Rc;
+
+use crate::{
+    AppC, ArgType, Arity, Binding, BoolC, CloV, Config, Env, ExactC, ExpansionC, ExprC, HostV, IdC,
+    IfC, IfForm, LamC, MatchC, NumC, Pattern, PrimV, QuasiC, QuoteC, StringC, Template, ValC,
+    Value, apply_host, apply_value, base_env, build_template, datum_value, force, interp_prim,
+    is_reserved, match_pattern, serialize, struct_type_named,
+};
+
+// Op - one bytecode instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    // push constant n of the Program
+    Const(usize),
+    // push slot index of the frame depth levels out from the current one
+    LoadLocal(usize, usize),
+    // push slot n of the globals
+    LoadGlobal(usize),
+    // panics with an unbound identifier error when executed
+    Unbound(String),
+    // push a closure over the current frame for proto n of the Program
+    MakeClosure(usize),
+    // pop argc args and a function, and call it
+    Call(usize),
+    // like Call, but replaces the current frame
+    TailCall(usize),
+    // pop argc args and apply the primitive directly
+    Prim(PrimV, usize),
+    // continue at the given instruction
+    Jump(usize),
+    // pop a boolean and continue at the given instruction if it is false,
+    // errors name the form the test is from
+    JumpIfFalse(usize, IfForm),
+    // pop the result and return it to the caller
+    Return,
+    // match the value on top of the stack, leaving it there, and push a frame of the pattern's ids,
+    // or continue at the given instruction if it does not match
+    Match(Pattern, usize),
+    // leave the frame pushed by Match
+    PopScope,
+    // drop the value on top of the stack
+    Pop,
+    // pop the value no match clause matched and panic
+    NoMatch,
+    // pop the values of the template's unquoted expressions and push the quasiquoted value
+    Quasi(Template),
+}
+
+// Proto - a compiled lambda body, along with its source for converting closures back into CloV
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proto {
+    pub params: Vec<String>,
+    pub code: Vec<Op>,
+    pub source: LamC,
+}
+
+// Program - compiled code: the constants, the lambda bodies, and the top-level code in main
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub consts: Vec<Value>,
+    pub protos: Vec<Rc<Proto>>,
+    pub main: Rc<Proto>,
+    pub globals: Env,
+}
+
+// VmValue - a runtime value, closures hold their compiled proto and captured frame
+#[derive(Debug, Clone)]
+enum VmValue {
+    Val(Value),
+    Clo(Rc<VmClosure>),
+}
+
+// VmClosure - a compiled lambda, the Program it was compiled in and the frame it was created in
+#[derive(Debug)]
+struct VmClosure {
+    program: Rc<Program>,
+    proto: Rc<Proto>,
+    env: Option<Rc<Frame>>,
+}
+
+// Frame - the argument slots of one call, linked to the frame its closure was created in
+#[derive(Debug)]
+struct Frame {
+    names: Vec<String>,
+    slots: Vec<VmValue>,
+    parent: Option<Rc<Frame>>,
+}
+
+// CallFrame - a pending call: the code being run and its Program, where we are in it, and its locals
+struct CallFrame {
+    program: Rc<Program>,
+    proto: Rc<Proto>,
+    pc: usize,
+    env: Option<Rc<Frame>>,
+}
+
+// vm_interp - compiles e and runs it in the default base_env, returning a Value
+pub fn vm_interp(e: &ExprC) -> Value {
+    run(&compile(e, base_env(Config::default())))
+}
+
+// compile - compiles e to a Program whose free ids are resolved against globals
+pub fn compile(e: &ExprC, globals: Env) -> Program {
+    let mut compiler = Compiler {
+        consts: Vec::new(),
+        protos: Vec::new(),
+        globals: &globals,
+    };
+    let mut code = Vec::new();
+    compiler.compile(e, &[], &mut code, true);
+    code.push(Op::Return);
+    let main = Rc::new(Proto {
+        params: Vec::new(),
+        code,
+        source: LamC {
+            args: Vec::new(),
+            body: Box::new(e.clone()),
+        },
+    });
+    Program {
+        consts: compiler.consts,
+        protos: compiler.protos,
+        main,
+        globals,
+    }
+}
+
+struct Compiler<'a> {
+    consts: Vec<Value>,
+    protos: Vec<Rc<Proto>>,
+    globals: &'a Env,
+}
+
+impl Compiler<'_> {
+    // compile - appends code for e to code, scope holds the params of each enclosing lambda, innermost last
+    fn compile(&mut self, e: &ExprC, scope: &[&[String]], code: &mut Vec<Op>, tail: bool) {
+        match e {
+            ExprC::NumC(NumC { n }) => {
+                code.push(Op::Const(self.constant(Value::Real(*n))));
+            }
+            ExprC::ExactC(ExactC { n }) => {
+                code.push(Op::Const(self.constant(Value::Exact(n.clone()))));
+            }
+            ExprC::StringC(StringC { s }) => {
+                code.push(Op::Const(self.constant(Value::String(s.clone()))));
+            }
+            ExprC::BoolC(BoolC { b }) => {
+                code.push(Op::Const(self.constant(Value::Boolean(*b))));
+            }
+            ExprC::VoidC => {
+                code.push(Op::Const(self.constant(Value::Void)));
+            }
+            ExprC::ValC(ValC { v }) => {
+                code.push(Op::Const(self.constant(v.clone())));
+            }
+            // the VM runs the expansion of a macro use as it is, its errors do not name the use
+            ExprC::ExpansionC(ExpansionC { e, .. }) => self.compile(e, scope, code, tail),
+            ExprC::QuoteC(QuoteC { datum }) => {
+                code.push(Op::Const(self.constant(datum_value(datum))));
+            }
+            ExprC::QuasiC(QuasiC { template }) => {
+                for e in template.unquoted() {
+                    self.compile(e, scope, code, false);
+                }
+                code.push(Op::Quasi(template.clone()));
+            }
+            ExprC::IdC(IdC { name }) => {
+                if is_reserved(name) {
+                    panic!("SHEQ: id name is a reserved word, got {}", name);
+                }
+                code.push(self.resolve(name, scope));
+            }
+            ExprC::IfC(IfC {
+                v,
+                iftrue,
+                iffalse,
+                form,
+            }) => {
+                self.compile(v, scope, code, false);
+                let jump_false = code.len();
+                code.push(Op::JumpIfFalse(0, *form));
+                self.compile(iftrue, scope, code, tail);
+                let jump_end = code.len();
+                code.push(Op::Jump(0));
+                code[jump_false] = Op::JumpIfFalse(code.len(), *form);
+                self.compile(iffalse, scope, code, tail);
+                code[jump_end] = Op::Jump(code.len());
+            }
+            ExprC::LamC(lam) => {
+                let mut inner_scope = scope.to_vec();
+                inner_scope.push(&lam.args);
+                let mut body = Vec::new();
+                self.compile(&lam.body, &inner_scope, &mut body, true);
+                body.push(Op::Return);
+                self.protos.push(Rc::new(Proto {
+                    params: lam.args.clone(),
+                    code: body,
+                    source: lam.clone(),
+                }));
+                code.push(Op::MakeClosure(self.protos.len() - 1));
+            }
+            ExprC::AppC(AppC { expr, args }) => {
+                // a primitive named directly is applied without building a call frame
+                if let ExprC::IdC(IdC { name }) = &**expr
+                    && let Op::LoadGlobal(slot) = self.resolve(name, scope)
+                    && let Value::PrimV(prim) = &*self.globals[slot].val
+                {
+                    let prim = prim.clone();
+                    for a in args {
+                        self.compile(a, scope, code, false);
+                    }
+                    code.push(Op::Prim(prim, args.len()));
+                    return;
+                }
+                self.compile(expr, scope, code, false);
+                for a in args {
+                    self.compile(a, scope, code, false);
+                }
+                code.push(if tail {
+                    Op::TailCall(args.len())
+                } else {
+                    Op::Call(args.len())
+                });
+            }
+            ExprC::MatchC(MatchC { v, clauses }) => {
+                // the scrutinee stays on the stack until a clause's guard passes
+                self.compile(v, scope, code, false);
+                let mut jump_ends = Vec::new();
+                for clause in clauses {
+                    let vars = clause.pat.vars();
+                    let mut inner_scope = scope.to_vec();
+                    inner_scope.push(&vars);
+                    let match_at = code.len();
+                    code.push(Op::Match(clause.pat.clone(), 0));
+                    let mut guard_fail = None;
+                    if let Some(guard) = &clause.guard {
+                        self.compile(guard, &inner_scope, code, false);
+                        guard_fail = Some(code.len());
+                        code.push(Op::JumpIfFalse(0, IfForm::If));
+                    }
+                    code.push(Op::Pop);
+                    self.compile(&clause.body, &inner_scope, code, tail);
+                    code.push(Op::PopScope);
+                    jump_ends.push(code.len());
+                    code.push(Op::Jump(0));
+                    if let Some(at) = guard_fail {
+                        code[at] = Op::JumpIfFalse(code.len(), IfForm::If);
+                        code.push(Op::PopScope);
+                    }
+                    code[match_at] = Op::Match(clause.pat.clone(), code.len());
+                }
+                code.push(Op::NoMatch);
+                for at in jump_ends {
+                    code[at] = Op::Jump(code.len());
+                }
+            }
+        }
+    }
+
+    // resolve - the instruction that loads name, searching locals innermost first, then the globals
+    fn resolve(&self, name: &str, scope: &[&[String]]) -> Op {
+        for (depth, params) in scope.iter().rev().enumerate() {
+            if let Some(index) = params.iter().position(|p| p == name) {
+                return Op::LoadLocal(depth, index);
+            }
+        }
+        match self.globals.iter().rposition(|b| b.name == name) {
+            Some(slot) => Op::LoadGlobal(slot),
+            None => Op::Unbound(name.to_string()),
+        }
+    }
+
+    fn constant(&mut self, v: Value) -> usize {
+        self.consts.push(v);
+        self.consts.len() - 1
+    }
+}
+
+// run - executes a compiled Program, returning the Value of its main code
+pub fn run(program: &Program) -> Value {
+    execute(&Rc::new(program.clone()), program.main.clone(), None).0
+}
+
+// execute - runs the code of proto with the given locals until it returns, also returning how many
+// instructions were executed
+fn execute(program: &Rc<Program>, proto: Rc<Proto>, env: Option<Rc<Frame>>) -> (Value, usize) {
+    let mut executed = 0;
+    let mut stack: Vec<VmValue> = Vec::new();
+    let mut frames: Vec<CallFrame> = Vec::new();
+    let mut current = CallFrame {
+        program: program.clone(),
+        proto,
+        pc: 0,
+        env,
+    };
+
+    loop {
+        let program = current.program.clone();
+        let proto = current.proto.clone();
+        let op = &proto.code[current.pc];
+        current.pc += 1;
+        executed += 1;
+        match op {
+            Op::Const(n) => stack.push(VmValue::Val(program.consts[*n].clone())),
+            Op::LoadLocal(depth, index) => {
+                let mut frame = current.env.as_ref().expect("SHEQ: no local frame");
+                for _ in 0..*depth {
+                    frame = frame.parent.as_ref().expect("SHEQ: no enclosing frame");
+                }
+                stack.push(frame.slots[*index].clone());
+            }
+            // a top-level definition is bound to a Thunk
+            Op::LoadGlobal(slot) => {
+                stack.push(VmValue::Val(force((*program.globals[*slot].val).clone())));
+            }
+            Op::Unbound(name) => panic!("SHEQ: unbound identifier '{}'", name),
+            Op::MakeClosure(n) => stack.push(VmValue::Clo(Rc::new(VmClosure {
+                program: program.clone(),
+                proto: program.protos[*n].clone(),
+                env: current.env.clone(),
+            }))),
+            Op::Call(argc) | Op::TailCall(argc) => {
+                let args = stack.split_off(stack.len() - argc);
+                let f = stack.pop().expect("SHEQ: missing function on stack");
+                let clo = match f {
+                    VmValue::Clo(clo) => clo,
+                    // a closure bound in the globals or taken out of a data structure, e.g. the first
+                    // of a list, runs in a Program of its own
+                    VmValue::Val(Value::CloV(clo)) => recompile(&clo),
+                    VmValue::Val(Value::PrimV(prim)) => {
+                        stack.push(VmValue::Val(apply_prim(&prim, args, &program)));
+                        continue;
+                    }
+                    VmValue::Val(Value::HostV(host)) => {
+                        stack.push(VmValue::Val(apply_host(&host, to_values(args))));
+                        continue;
+                    }
+                    // a procedure made by define-struct
+                    VmValue::Val(f @ Value::StructOp(_)) => {
+                        let result =
+                            apply_value(f, to_values(args), &program.globals, Config::default());
+                        stack.push(VmValue::Val(result));
+                        continue;
+                    }
+                    VmValue::Val(other) => {
+                        panic!("SHEQ: attempted to apply non function value of {:?}", other)
+                    }
+                };
+                if args.len() != clo.proto.params.len() {
+                    panic!(
+                        "SHEQ: Incorrect number of arguments, got {}, expected {}",
+                        args.len(),
+                        clo.proto.params.len()
+                    );
+                }
+                let callee = CallFrame {
+                    program: clo.program.clone(),
+                    proto: clo.proto.clone(),
+                    pc: 0,
+                    env: Some(Rc::new(Frame {
+                        names: clo.proto.params.clone(),
+                        slots: args,
+                        parent: clo.env.clone(),
+                    })),
+                };
+                let caller = std::mem::replace(&mut current, callee);
+                if matches!(op, Op::Call(_)) {
+                    frames.push(caller);
+                }
+            }
+            Op::Prim(prim, argc) => {
+                let args = stack.split_off(stack.len() - argc);
+                stack.push(VmValue::Val(apply_prim(prim, args, &program)));
+            }
+            Op::Jump(target) => current.pc = *target,
+            Op::JumpIfFalse(target, form) => match stack.pop() {
+                Some(VmValue::Val(Value::Boolean(b))) => {
+                    if !b {
+                        current.pc = *target;
+                    }
+                }
+                Some(other) => {
+                    panic!(
+                        "SHEQ: {} expected boolean test, got {}",
+                        form.name(),
+                        serialize(&to_value(other))
+                    )
+                }
+                None => panic!("SHEQ: missing if test on stack"),
+            },
+            Op::Match(pat, fail) => {
+                let v = stack.last().expect("SHEQ: missing match value on stack");
+                let slots = match (pat, v) {
+                    (Pattern::Wildcard, _) => Some(Vec::new()),
+                    (Pattern::Id(_), v) => Some(vec![v.clone()]),
+                    (_, VmValue::Clo(_)) => None,
+                    (pat, VmValue::Val(v)) => {
+                        let mut binds = Vec::new();
+                        match_pattern(
+                            pat,
+                            v,
+                            &|name| struct_type_named(name, &program.globals),
+                            &mut binds,
+                        )
+                        .then(|| binds.into_iter().map(VmValue::Val).collect())
+                    }
+                };
+                match slots {
+                    Some(slots) => {
+                        current.env = Some(Rc::new(Frame {
+                            names: pat.vars(),
+                            slots,
+                            parent: current.env.take(),
+                        }))
+                    }
+                    None => current.pc = *fail,
+                }
+            }
+            Op::PopScope => {
+                let frame = current.env.take().expect("SHEQ: no match frame");
+                current.env = frame.parent.clone();
+            }
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::NoMatch => {
+                let v = stack.pop().expect("SHEQ: missing match value on stack");
+                panic!("SHEQ: no match clause matched {}", serialize(&to_value(v)))
+            }
+            Op::Quasi(template) => {
+                let n = template.unquoted().len();
+                let mut vals = to_values(stack.split_off(stack.len() - n)).into_iter();
+                let v = build_template(template, &mut |_| {
+                    vals.next().expect("SHEQ: missing unquoted value on stack")
+                });
+                stack.push(VmValue::Val(v));
+            }
+            Op::Return => match frames.pop() {
+                Some(caller) => current = caller,
+                None => {
+                    let result = stack.pop().expect("SHEQ: missing result on stack");
+                    return (to_value(result), executed);
+                }
+            },
+        }
+    }
+}
+
+// apply_prim - applies a primitive, a closure in a procedure argument is passed as a callback
+fn apply_prim(prim: &PrimV, args: Vec<VmValue>, program: &Rc<Program>) -> Value {
+    let types = prim.op.arg_types();
+    let args = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| match v {
+            VmValue::Clo(clo) if types.get(i) == Some(&ArgType::Procedure) => callback(clo),
+            VmValue::Val(Value::CloV(clo)) if types.get(i) == Some(&ArgType::Procedure) => {
+                callback(recompile(&clo))
+            }
+            v => to_value(v),
+        })
+        .collect();
+    interp_prim(prim, args, &program.globals, Config::default())
+}
+
+// recompile - compiles the lambda of clo with its Env as the globals of a new Program,
+// returning a VM closure with no frame of its own, whose captured variables are those globals
+fn recompile(clo: &CloV) -> Rc<VmClosure> {
+    let lam = ExprC::LamC(LamC {
+        args: clo.params.clone(),
+        body: clo.body.clone(),
+    });
+    let program = compile(&lam, clo.env.clone());
+    // the lambda's own proto is compiled after the lambdas in its body
+    let proto = program
+        .protos
+        .last()
+        .expect("SHEQ: lambda without a proto")
+        .clone();
+    Rc::new(VmClosure {
+        program: Rc::new(program),
+        proto,
+        env: None,
+    })
+}
+
+// callback - a HostV that runs clo in the VM, named like the CloV it stands for in errors
+fn callback(clo: Rc<VmClosure>) -> Value {
+    let params = &clo.proto.params;
+    Value::HostV(HostV {
+        name: format!("{{lambda ({}) ...}}", params.join(" ")),
+        arity: Arity::Exact(params.len()),
+        func: Rc::new(move |args| {
+            let env = Frame {
+                names: clo.proto.params.clone(),
+                slots: args.into_iter().map(VmValue::Val).collect(),
+                parent: clo.env.clone(),
+            };
+            execute(&clo.program, clo.proto.clone(), Some(Rc::new(env))).0
+        }),
+    })
+}
+
+fn to_values(args: Vec<VmValue>) -> Vec<Value> {
+    args.into_iter().map(to_value).collect()
+}
+
+// to_value - converts a VmValue into the Value interp would produce, rebuilding a closure's Env
+// from its frames on top of the globals of the Program it was compiled in
+fn to_value(v: VmValue) -> Value {
+    match v {
+        VmValue::Val(v) => v,
+        VmValue::Clo(clo) => {
+            let mut frames = Vec::new();
+            let mut frame = clo.env.clone();
+            while let Some(f) = frame {
+                frame = f.parent.clone();
+                frames.push(f);
+            }
+            let mut env = clo.program.globals.clone();
+            for f in frames.iter().rev() {
+                for (name, slot) in f.names.iter().zip(f.slots.iter()) {
+                    env.push(Binding {
+                        name: name.clone(),
+                        val: Box::new(to_value(slot.clone())),
+                    });
+                }
+            }
+            Value::CloV(CloV {
+                params: clo.proto.source.args.clone(),
+                body: clo.proto.source.body.clone(),
+                env,
+            })
+        }
+    }
+}
+
+// TESTS
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse, read};
+    use crate::{interp, serialize, top_env};
+
+    fn vm_top(src: &str) -> String {
+        serialize(&vm_interp(&parse(&read(src))))
+    }
+
+    // fib through self-application, there is no recursive binding form
+    const FIB: &str = "{let [fib = {lambda (self n)
+                                      {if {<= n 1}
+                                          n
+                                          {+ {self self {- n 1}} {self self {- n 2}}}}}]
+                        in {fib fib N} end}";
+
+    #[test]
+    fn vm_calls_closure_from_list() {
+        let e = parse(&read("{{first {list {lambda (x) {* x 2}}}} 21}"));
+        assert_eq!(vm_interp(&e), Value::Exact(crate::Rational::from(42)));
+    }
+
+    #[test]
+    fn vm_match() {
+        let env = top_env();
+        for src in [
+            "{match {list 1 2 3} [{list a b} a] [{cons a rest} rest]}",
+            "{match 5 [0 \"zero\"] [n #:when {< n 0} \"neg\"] [n {* n n}]}",
+            "{match {lambda (x) x} [\"f\" 0] [f {f 7}]}",
+            "{{lambda (y) {match y [x {lambda (z) {+ x z}}]}} 1}",
+        ] {
+            let e = parse(&read(src));
+            assert_eq!(run(&compile(&e, env.clone())), interp(&e, &env), "{}", src);
+        }
+        let count = "{let [loop = {lambda (self n) {match n [0 \"done\"] [_ {self self {- n 1}}]}}]
+                      in {loop loop 100000} end}";
+        assert_eq!(vm_top(count), "\"done\"");
+    }
+
+    #[test]
+    fn vm_derived_forms() {
+        assert_eq!(vm_top("{cond [{< 2 1} 1] [{or false true} 2]}"), "2");
+        assert_eq!(vm_top("{and true {when false {/ 1 0}}}"), "#<void>");
+    }
+
+    #[test]
+    #[should_panic(expected = "or expected boolean test, got 1")]
+    fn vm_or_non_bool_test() {
+        let _ = vm_top("{or 1 2}");
+    }
+
+    #[test]
+    fn vm_closure_keeps_env() {
+        match vm_interp(&parse(&read("{{lambda (x) {lambda (y) {+ x y}}} 3}"))) {
+            Value::CloV(clo) => {
+                assert_eq!(clo.params, vec!["y".to_string()]);
+                assert_eq!(
+                    crate::get_binding_val("x", &clo.env),
+                    Value::Exact(crate::Rational::from(3))
+                );
+                // the rest of its Env is the globals the program was compiled against
+                assert!(matches!(
+                    force(crate::get_binding_val("range", &clo.env)),
+                    Value::CloV(_)
+                ));
+            }
+            other => panic!("SHEQ: Expected closure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn vm_sees_prelude() {
+        assert_eq!(vm_top("{length {range 0 5000}}"), "5000");
+        assert_eq!(vm_top("{{compose first reverse} {list 1 2 3}}"), "3");
+    }
+
+    #[test]
+    fn vm_calls_back_closures() {
+        assert_eq!(
+            vm_top("{{lambda (k) {map {lambda (x) {+ x k}} {list 1 2}}} 10}"),
+            "'(11 12)"
+        );
+        assert_eq!(
+            vm_top("{foldl {lambda (x acc) {cons x acc}} empty {list 1 2 3}}"),
+            "'(3 2 1)"
+        );
+        // the callback runs in the VM, whose tail calls do not grow the stack the way interp's do
+        let deep = "{let [loop = {lambda (self n) {if {<= n 0} n {self self {- n 1}}}}]
+                     in {build-list 2 {lambda (i) {loop loop 100000}}} end}";
+        assert_eq!(vm_top(deep), "'(0 0)");
+    }
+
+    #[test]
+    fn vm_runs_clov_in_vm() {
+        assert_eq!(
+            vm_top("{map {first {list {lambda (x) {* x 2}}}} {list 1 2}}"),
+            "'(2 4)"
+        );
+        assert_eq!(
+            vm_top("{{compose {curry +} first} {list 1 2}}"),
+            "#<procedure>"
+        );
+        assert_eq!(vm_top("{{{compose {curry +} first} {list 1 2}} 3}"), "4");
+        // loop is a CloV once it has been through the list, its tail calls still reuse the frame
+        let deep = "{let [loop = {lambda (self n) {if {<= n 0} n {self self {- n 1}}}}]
+                     in {let [fs = {list loop}] in {{first fs} {first fs} 20000} end} end}";
+        assert_eq!(vm_top(deep), "0");
+    }
+
+    #[test]
+    #[should_panic(expected = "SHEQ: map cannot call {lambda (x y) ...} with 1 argument")]
+    fn vm_callback_arity() {
+        let _ = vm_top("{map {lambda (x y) x} {list 1 2}}");
+    }
+
+    #[test]
+    fn vm_fib() {
+        assert_eq!(vm_top(&FIB.replace("N", "15")), "610");
+    }
+
+    // run with cargo test --release -- --ignored --nocapture vm_faster_than_interp
+    #[test]
+    #[ignore]
+    fn vm_faster_than_interp() {
+        let e = parse(&read(&FIB.replace("N", "25")));
+        let env = top_env();
+        let start = std::time::Instant::now();
+        let interp_val = interp(&e, &env);
+        let interp_time = start.elapsed();
+        let program = compile(&e, env);
+        let start = std::time::Instant::now();
+        let vm_val = run(&program);
+        let vm_time = start.elapsed();
+        println!("fib(25): interp {:?}, vm {:?}", interp_time, vm_time);
+        assert_eq!(vm_val, interp_val);
+        assert!(vm_time < interp_time);
+    }
+
+    #[test]
+    fn vm_tail_calls_do_not_grow() {
+        let count = "{let [loop = {lambda (self n) {if {<= n 0} \"done\" {self self {- n 1}}}}]
+                      in {loop loop 100000} end}";
+        assert_eq!(vm_top(count), "\"done\"");
+    }
+
+    #[test]
+    #[should_panic(expected = "id name is a reserved word")]
+    fn vm_reserved_word_error() {
+        let _ = vm_interp(&ExprC::IdC(IdC { name: "if".into() }));
+    }
+
+    #[test]
+    #[should_panic(expected = "if expected boolean test")]
+    fn vm_non_bool_test() {
+        let _ = vm_top("{if 1 1 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "attempted to apply non function value")]
+    fn vm_non_fval_error() {
+        let _ = vm_top("{1}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Incorrect number of arguments")]
+    fn vm_wrong_arity() {
+        let _ = vm_top("{{lambda (x) x} 1 2}");
+    }
+
+    #[test]
+    #[should_panic(expected = "unbound identifier 'y'")]
+    fn vm_unbound() {
+        let _ = vm_top("{{lambda (x) y} 1}");
+    }
+
+    #[test]
+    fn vm_unbound_not_evaluated() {
+        assert_eq!(vm_top("{if true 1 y}"), "1");
+    }
+
+    // the VM does the same work for every call, however deep the recursion is, so the instructions a
+    // recursive sum executes grow by the same amount for every number it adds
+    #[test]
+    fn vm_instructions_per_call() {
+        let executed = |n: usize| {
+            let src = format!(
+                "{{let [sum = {{lambda (self n) {{if {{<= n 0}} 0 {{+ n {{self self {{- n 1}}}}}}}}}}]
+                  in {{sum sum {}}} end}}",
+                n
+            );
+            let e = parse(&read(&src));
+            let program = Rc::new(compile(&e, top_env()));
+            let (v, executed) = execute(&program, program.main.clone(), None);
+            assert_eq!(
+                v,
+                Value::Exact(crate::Rational::from((n * (n + 1) / 2) as i64))
+            );
+            executed
+        };
+        let per_call = executed(200) - executed(100);
+        assert_eq!(per_call % 100, 0);
+        let per_call = per_call / 100;
+        assert_eq!(executed(5000), executed(100) + 4900 * per_call);
+        assert!(per_call <= 20, "{} instructions per call", per_call);
+    }
+}